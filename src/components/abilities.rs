@@ -0,0 +1,216 @@
+use amethyst::{
+    core::{math::Vector2, Transform},
+    ecs::{
+        Component, DenseVecStorage, Entities, Join, LazyUpdate, Read, ReadStorage, System,
+        WriteStorage,
+    },
+    input::{InputHandler, StringBindings},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::game_data::FixedTimestep;
+use crate::geometry::Corners;
+use super::combat::{
+    spawn_projectile, AttackData, Attacker, BoxShape, HitboxShape, ProjectileSpec,
+    ProjectileSprites,
+};
+use super::physics::{BoundingBox2D, CollisionLayers, PhysicsBox};
+use super::player::{Player, PowerUp};
+use super::player_state::PlayerState;
+
+/// Tuning for the power-up abilities.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AbilityConfig {
+    /// KiStar: how fast thrown stars fly.
+    pub star_speed: f32,
+    pub star_lifetime: f32,
+    pub star_cooldown: f32,
+    /// How many enemies a star passes through before it's used up.
+    pub star_pierce: u32,
+    /// KiBlade: frame data for the slash.
+    pub slash: AttackData,
+    pub slash_cooldown: f32,
+    /// KiFan: fastest fall while gliding.
+    pub glide_fall_speed: f32,
+}
+
+impl Default for AbilityConfig {
+    fn default() -> Self {
+        Self {
+            star_speed: 200.0,
+            star_lifetime: 1.0,
+            star_cooldown: 0.4,
+            star_pierce: 0,
+            slash: AttackData {
+                frame_time: 0.05,
+                frame_count: 4,
+                hitboxes: vec![HitboxShape {
+                    shape: BoxShape {
+                        offset: Vector2::new(8.0, 2.0),
+                        size: Vector2::new(14.0, 12.0),
+                    },
+                    frames: (1, 2),
+                    damage: 1,
+                    knockback: Vector2::new(80.0, 60.0),
+                    hit_stop: 0.05,
+                }],
+            },
+            slash_cooldown: 0.35,
+            glide_fall_speed: 25.0,
+        }
+    }
+}
+
+const STAR_SPRITE: usize = 1;
+
+/// Ability input and timers for a player.
+#[derive(Debug, Default)]
+pub struct Abilities {
+    // Set by AbilityInputSystem
+    pub throw_edge: bool,
+    pub slash_edge: bool,
+    pub grab: bool,
+    pub glide: bool,
+    throw_held: bool,
+    slash_held: bool,
+
+    // Set by AbilitySystem
+    pub cooldown: f32,
+}
+
+impl Component for Abilities {
+    type Storage = DenseVecStorage<Self>;
+}
+
+lazy_static! {
+    static ref STAR_BBOX: BoundingBox2D = {
+        BoundingBox2D {
+            corners: Corners {
+                bottom_left: Vector2::new(-2.0, -2.0),
+                top_right: Vector2::new(2.0, 2.0),
+            },
+        }
+    };
+}
+
+pub struct AbilityInputSystem;
+
+impl<'s> System<'s> for AbilityInputSystem {
+    type SystemData = (
+        WriteStorage<'s, Abilities>,
+        Read<'s, InputHandler<StringBindings>>,
+    );
+
+    fn run(&mut self, (mut abilities, input): Self::SystemData) {
+        for abilities in (&mut abilities).join() {
+            let throw_down = input.action_is_down("throw").unwrap_or(false);
+            abilities.throw_edge = throw_down && !abilities.throw_held;
+            abilities.throw_held = throw_down;
+
+            let slash_down = input.action_is_down("slash").unwrap_or(false);
+            abilities.slash_edge = slash_down && !abilities.slash_held;
+            abilities.slash_held = slash_down;
+
+            abilities.grab = input.action_is_down("grab").unwrap_or(false);
+            abilities.glide = input.action_is_down("glide").unwrap_or(false);
+        }
+    }
+}
+
+/// Uses whichever ability the player's power-up grants. Runs after the player's velocity is
+/// decided so clinging and gliding can override it.
+#[derive(SystemDesc)]
+pub struct AbilitySystem;
+
+impl<'s> System<'s> for AbilitySystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, Abilities>,
+        WriteStorage<'s, Attacker>,
+        WriteStorage<'s, Player>,
+        WriteStorage<'s, PhysicsBox>,
+        ReadStorage<'s, Transform>,
+        Read<'s, AbilityConfig>,
+        Option<Read<'s, ProjectileSprites>>,
+        Read<'s, LazyUpdate>,
+        Read<'s, FixedTimestep>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut abilities,
+            mut attackers,
+            mut players,
+            mut physics_boxes,
+            transforms,
+            config,
+            sprites,
+            lazy,
+            timestep,
+        ): Self::SystemData,
+    ) {
+        let time_step = timestep.step;
+
+        for (entity, abilities, attacker, player, physics, transform) in (
+            &entities,
+            &mut abilities,
+            &mut attackers,
+            &mut players,
+            &mut physics_boxes,
+            &transforms,
+        )
+            .join()
+        {
+            abilities.cooldown = (abilities.cooldown - time_step).max(0.0);
+            player.clinging = false;
+            if !player.state.has_control() {
+                continue;
+            }
+
+            let position = Vector2::new(transform.translation().x, transform.translation().y);
+            let body = physics.bbox.translate(position);
+
+            match player.power_up {
+                Some(PowerUp::KiStar) if abilities.throw_edge && abilities.cooldown <= 0.0 => {
+                    abilities.cooldown = config.star_cooldown;
+                    if let Some(sprites) = sprites.as_ref() {
+                        let start = Vector2::new(body.corners.x_midpoint(), body.corners.y_midpoint());
+                        let spec = star_spec(player.facing, &config);
+                        spawn_projectile(&entities, &lazy, sprites, entity, start, &spec);
+                    }
+                }
+                Some(PowerUp::KiBlade) if abilities.slash_edge && abilities.cooldown <= 0.0 => {
+                    abilities.cooldown = config.slash_cooldown;
+                    attacker.start(&config.slash);
+                }
+                // Holds the player still on the wall until they let go of grab or jump off.
+                Some(PowerUp::KiClaws)
+                    if abilities.grab && player.state == PlayerState::WallSlide =>
+                {
+                    player.clinging = true;
+                    physics.velocity.y = 0.0;
+                }
+                Some(PowerUp::KiFan) if abilities.glide && !player.on_ground => {
+                    physics.velocity.y = physics.velocity.y.max(-config.glide_fall_speed);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn star_spec(facing: f32, config: &AbilityConfig) -> ProjectileSpec {
+    ProjectileSpec {
+        sprite_number: STAR_SPRITE,
+        bbox: *STAR_BBOX,
+        velocity: Vector2::new(facing * config.star_speed, 0.0),
+        gravity_scale: 0.0,
+        lifetime: config.star_lifetime,
+        pierce: config.star_pierce,
+        damage: 1,
+        layers: CollisionLayers::projectile(),
+    }
+}
@@ -0,0 +1,188 @@
+use amethyst::{
+    animation::{
+        get_animation_set, AnimationCommand, AnimationControlSet, AnimationSet,
+        AnimationSetPrefab, EndControl,
+    },
+    assets::PrefabData,
+    core::Transform,
+    ecs::{Component, DenseVecStorage, Entities, Join, ReadStorage, System, WriteStorage},
+    error::Error,
+    renderer::{sprite::prefab::SpriteScenePrefab, SpriteRender},
+};
+use serde::{Deserialize, Serialize};
+
+use super::combat::Attacker;
+use super::physics::PhysicsBox;
+use super::player::Player;
+use super::player_state::PlayerState;
+
+/// Slowest and fastest a speed scaled animation plays, relative to its authored rate.
+const MIN_RATE: f32 = 0.5;
+const MAX_RATE: f32 = 2.0;
+
+/// Animation ids used in a AnimationSet
+#[derive(Eq, PartialOrd, PartialEq, Hash, Debug, Copy, Clone, Deserialize, Serialize)]
+pub enum AnimationId {
+    Stand,
+    Walk,
+    Run,
+    Stop,
+    Crouch,
+    Jump,
+    Fall,
+    WallSlide,
+    Climb,
+    Damaged,
+    Dying,
+    /// Keyed on attack frames, and played by the attack's timer rather than by time passing.
+    Slash,
+}
+
+impl AnimationId {
+    /// Cycles loop, everything else plays once and holds its last frame.
+    fn end_control(self) -> EndControl {
+        match self {
+            AnimationId::Walk
+            | AnimationId::Run
+            | AnimationId::Climb
+            | AnimationId::Fall
+            | AnimationId::WallSlide => EndControl::Loop(None),
+            _ => EndControl::Stay,
+        }
+    }
+}
+
+impl From<PlayerState> for AnimationId {
+    fn from(state: PlayerState) -> Self {
+        match state {
+            PlayerState::Idle => AnimationId::Stand,
+            PlayerState::Walk => AnimationId::Walk,
+            PlayerState::Run => AnimationId::Run,
+            PlayerState::Stop => AnimationId::Stop,
+            PlayerState::Crouch => AnimationId::Crouch,
+            PlayerState::Jump => AnimationId::Jump,
+            PlayerState::Fall => AnimationId::Fall,
+            PlayerState::WallSlide => AnimationId::WallSlide,
+            PlayerState::Climb => AnimationId::Climb,
+            PlayerState::Hurt => AnimationId::Damaged,
+            PlayerState::Dead => AnimationId::Dying,
+        }
+    }
+}
+
+/// Loading data for one entity
+#[derive(Debug, Clone, Deserialize, PrefabData)]
+pub struct SpritePrefab {
+    /// Information for rendering a scene with sprites
+    sprite_scene: SpriteScenePrefab,
+    /// Аll animations that can be run on the entity
+    animation_set: AnimationSetPrefab<AnimationId, SpriteRender>,
+}
+
+/// The animation an entity is currently playing.
+#[derive(Debug, Default)]
+pub struct AnimationPlayback {
+    pub current: Option<AnimationId>,
+}
+
+impl Component for AnimationPlayback {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Plays the animation for the player's state, switching when the state changes. Walk,
+/// run and climb cycles play faster the faster the player moves. An attack overrides the state,
+/// showing the frame its hitboxes are counted from. The sprite is mirrored to face the way the
+/// player is facing.
+pub struct PlayerAnimationSystem;
+
+impl<'s> System<'s> for PlayerAnimationSystem {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Player>,
+        ReadStorage<'s, PhysicsBox>,
+        ReadStorage<'s, Attacker>,
+        ReadStorage<'s, AnimationSet<AnimationId, SpriteRender>>,
+        WriteStorage<'s, AnimationControlSet<AnimationId, SpriteRender>>,
+        WriteStorage<'s, AnimationPlayback>,
+        WriteStorage<'s, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            players,
+            physics_boxes,
+            attackers,
+            animation_sets,
+            mut control_sets,
+            mut playbacks,
+            mut transforms,
+        ): Self::SystemData,
+    ) {
+        for (entity, player, physics, animation_set, playback, transform) in (
+            &entities,
+            &players,
+            &physics_boxes,
+            &animation_sets,
+            &mut playbacks,
+            &mut transforms,
+        )
+            .join()
+        {
+            let scale = transform.scale().x.abs();
+            transform.scale_mut().x = scale * player.facing;
+
+            let attack = attackers
+                .get(entity)
+                .and_then(|attacker| attacker.attack.as_ref());
+            let next = match attack {
+                Some(_) => AnimationId::Slash,
+                None => AnimationId::from(player.state),
+            };
+            let control_set = match get_animation_set(&mut control_sets, entity) {
+                Some(control_set) => control_set,
+                None => continue,
+            };
+            if playback.current != Some(next) {
+                if let Some(current) = playback.current {
+                    control_set.abort(current);
+                }
+                if let Some(animation) = animation_set.get(&next) {
+                    // The slash is stepped through by hand below.
+                    let rate = if next == AnimationId::Slash { 0.0 } else { 1.0 };
+                    control_set.add_animation(
+                        next,
+                        animation,
+                        next.end_control(),
+                        rate,
+                        AnimationCommand::Start,
+                    );
+                }
+                playback.current = Some(next);
+            }
+            if let Some(attack) = attack {
+                control_set.set_input(AnimationId::Slash, attack.frame() as f32);
+            }
+            if let Some(rate) = speed_rate(next, player, physics) {
+                control_set.set_rate(next, rate);
+            }
+        }
+    }
+}
+
+/// How fast a movement cycle should play for the player's current speed.
+fn speed_rate(id: AnimationId, player: &Player, physics: &PhysicsBox) -> Option<f32> {
+    // Climbing holds still when the player stops on a ladder.
+    let (speed, max_speed, min_rate) = match id {
+        AnimationId::Walk => (physics.velocity.x.abs(), player.config.max_speed_walking, MIN_RATE),
+        AnimationId::Run => (physics.velocity.x.abs(), player.config.max_speed_running, MIN_RATE),
+        AnimationId::Climb => (physics.velocity.norm(), player.config.max_speed_climbing, 0.0),
+        _ => return None,
+    };
+    if max_speed <= 0.0 {
+        return None;
+    }
+    Some((speed / max_speed).max(min_rate).min(MAX_RATE))
+}
+
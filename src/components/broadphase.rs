@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use amethyst::{
+    core::{math::Vector2, Transform},
+    ecs::{Entities, Entity, Join, ReadStorage, System, SystemData, Write},
+};
+
+use super::physics::{
+    BoundingBox2D, CollisionLayers, KinematicBody, OneWayPlatform, PhysicsBox, Slope, Solid,
+};
+
+/// Width and height of a single spatial hash cell. Roughly two ground tiles.
+pub const CELL_SIZE: f32 = 32.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColliderKind {
+    /// A BoundingBox2D, stored in world coordinates.
+    Static,
+    /// A PhysicsBox translated by its entity's Transform.
+    Dynamic,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Collider {
+    pub entity: Entity,
+    pub bbox: BoundingBox2D,
+    pub kind: ColliderKind,
+    pub layers: CollisionLayers,
+    pub one_way: bool,
+    pub slope: Option<Slope>,
+    pub velocity: Vector2<f32>,
+}
+
+impl Collider {
+    pub fn is_static(&self) -> bool {
+        self.kind == ColliderKind::Static
+    }
+}
+
+/// A uniform grid over the world. Each cell lists the colliders overlapping it so queries only
+/// have to test colliders near the area of interest.
+#[derive(Debug)]
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    colliders: Vec<Collider>,
+}
+
+impl Default for SpatialHash {
+    fn default() -> Self {
+        SpatialHash::new(CELL_SIZE)
+    }
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            colliders: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.colliders.clear();
+    }
+
+    pub fn insert(&mut self, collider: Collider) {
+        let index = self.colliders.len();
+        let ((min_x, min_y), (max_x, max_y)) = self.cell_range(&collider.bbox);
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                self.cells.entry((x, y)).or_insert_with(Vec::new).push(index);
+            }
+        }
+        self.colliders.push(collider);
+    }
+
+    fn cell(&self, point: Vector2<f32>) -> (i32, i32) {
+        (
+            (point.x / self.cell_size).floor() as i32,
+            (point.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn cell_range(&self, bbox: &BoundingBox2D) -> ((i32, i32), (i32, i32)) {
+        (
+            self.cell(bbox.corners.bottom_left()),
+            self.cell(bbox.corners.top_right()),
+        )
+    }
+
+    /// Indices of every collider in the cells covered by `bbox`, without duplicates.
+    fn candidates(&self, bbox: &BoundingBox2D) -> Vec<usize> {
+        let ((min_x, min_y), (max_x, max_y)) = self.cell_range(bbox);
+        let mut indices = Vec::new();
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    indices.extend(cell.iter().cloned());
+                }
+            }
+        }
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+    }
+
+    /// All colliders overlapping `bbox`.
+    pub fn query_aabb(&self, bbox: &BoundingBox2D) -> Vec<&Collider> {
+        self.candidates(bbox)
+            .into_iter()
+            .map(|index| &self.colliders[index])
+            .filter(|collider| collider.bbox.intersects(bbox))
+            .collect()
+    }
+
+    /// The static colliders overlapping `bbox` which `layers` collides with.
+    pub fn query_statics(&self, bbox: &BoundingBox2D, layers: &CollisionLayers) -> Vec<Solid> {
+        self.query_aabb(bbox)
+            .into_iter()
+            .filter(|collider| collider.is_static() && layers.interacts_with(&collider.layers))
+            .map(|collider| Solid {
+                entity: collider.entity,
+                bbox: collider.bbox,
+                one_way: collider.one_way,
+                slope: collider.slope,
+                velocity: collider.velocity,
+            })
+            .collect()
+    }
+
+    /// All colliders containing `point`.
+    pub fn query_point(&self, point: Vector2<f32>) -> Vec<&Collider> {
+        let bbox = BoundingBox2D::new(point, 0.0, 0.0);
+        self.candidates(&bbox)
+            .into_iter()
+            .map(|index| &self.colliders[index])
+            .filter(|collider| collider.bbox.contains(point))
+            .collect()
+    }
+
+    /// All colliders crossed by `segment`, nearest first, along with the fraction of the
+    /// segment travelled before reaching each of them.
+    pub fn cast_segment(&self, segment: [Vector2<f32>; 2]) -> Vec<(f32, &Collider)> {
+        let start = BoundingBox2D::new(segment[0], 0.0, 0.0);
+        let direction = segment[1] - segment[0];
+        let covered_space = start.super_bounding_box(BoundingBox2D::new(segment[1], 0.0, 0.0));
+
+        let mut hits: Vec<(f32, &Collider)> = self
+            .candidates(&covered_space)
+            .into_iter()
+            .map(|index| &self.colliders[index])
+            .filter_map(|collider| {
+                if collider.bbox.contains(segment[0]) {
+                    Some((0.0, collider))
+                } else {
+                    start
+                        .sweep(direction, &collider.bbox)
+                        .map(|hit| (hit.time, collider))
+                }
+            })
+            .collect();
+        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        hits
+    }
+}
+
+/// Rebuilds the SpatialHash from every static and dynamic collider at the start of each step.
+#[derive(SystemDesc)]
+pub struct BroadphaseSystem;
+
+impl<'s> System<'s> for BroadphaseSystem {
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, BoundingBox2D>,
+        ReadStorage<'s, CollisionLayers>,
+        ReadStorage<'s, OneWayPlatform>,
+        ReadStorage<'s, Slope>,
+        ReadStorage<'s, KinematicBody>,
+        ReadStorage<'s, PhysicsBox>,
+        ReadStorage<'s, Transform>,
+        Write<'s, SpatialHash>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            bounding_boxes,
+            collision_layers,
+            one_way_platforms,
+            slopes,
+            kinematic_bodies,
+            physics_boxes,
+            transforms,
+            mut hash,
+        ): Self::SystemData,
+    ) {
+        hash.clear();
+        for (entity, bbox, layers, one_way, kinematic) in (
+            &entities,
+            &bounding_boxes,
+            collision_layers.maybe(),
+            one_way_platforms.maybe(),
+            kinematic_bodies.maybe(),
+        )
+            .join()
+        {
+            hash.insert(Collider {
+                entity,
+                bbox: *bbox,
+                kind: ColliderKind::Static,
+                layers: layers.cloned().unwrap_or_else(CollisionLayers::ground),
+                one_way: one_way.is_some(),
+                slope: None,
+                velocity: kinematic.map_or(Vector2::new(0.0, 0.0), |kinematic| kinematic.velocity),
+            });
+        }
+        for (entity, slope, layers) in (&entities, &slopes, collision_layers.maybe()).join() {
+            hash.insert(Collider {
+                entity,
+                bbox: slope.bbox(),
+                kind: ColliderKind::Static,
+                layers: layers.cloned().unwrap_or_else(CollisionLayers::ground),
+                one_way: false,
+                slope: Some(*slope),
+                velocity: Vector2::new(0.0, 0.0),
+            });
+        }
+        for (entity, physics, transform) in (&entities, &physics_boxes, &transforms).join() {
+            let position = Vector2::new(transform.translation().x, transform.translation().y);
+            hash.insert(Collider {
+                entity,
+                bbox: physics.bbox.translate(position),
+                kind: ColliderKind::Dynamic,
+                layers: physics.layers,
+                one_way: false,
+                slope: None,
+                velocity: physics.velocity,
+            });
+        }
+    }
+}
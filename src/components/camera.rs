@@ -1,16 +1,115 @@
+use super::arena::Arena;
+use super::physics::PhysicsBox;
 use super::player::Player;
+use super::player_state::PlayerState;
+use crate::geometry::Corners;
 use amethyst::{
-    core::{math::Vector3, Transform},
-    ecs::{Join, ReadStorage, System, SystemData, World, WriteStorage},
+    core::{math::Vector2, Time, Transform},
+    ecs::{
+        Component, DenseVecStorage, Join, Read, ReadStorage, System, SystemData, World, Write,
+        WriteStorage,
+    },
     prelude::*,
     renderer::Camera,
 };
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CameraConfig {
     pub width: f32,
     pub height: f32,
+    /// Size of the window in the middle of the view the player can move around in without the
+    /// camera following.
+    #[serde(default = "default_dead_zone")]
+    pub dead_zone: Vector2<f32>,
+    /// How many seconds of the player's horizontal velocity the camera looks ahead by.
+    #[serde(default = "default_look_ahead_time")]
+    pub look_ahead_time: f32,
+    #[serde(default = "default_max_look_ahead")]
+    pub max_look_ahead: f32,
+    /// How quickly the camera closes the distance to where it should be, per second. Zero
+    /// snaps straight there.
+    #[serde(default = "default_smoothing")]
+    pub smoothing: f32,
+    /// Furthest the camera is thrown off centre by a shake at full trauma.
+    #[serde(default = "default_max_shake_offset")]
+    pub max_shake_offset: f32,
+    /// Furthest the camera is rolled, in radians, by a shake at full trauma.
+    #[serde(default = "default_max_shake_angle")]
+    pub max_shake_angle: f32,
+    /// How much trauma wears off per second.
+    #[serde(default = "default_trauma_decay")]
+    pub trauma_decay: f32,
+    /// Trauma added whenever an attack lands.
+    #[serde(default = "default_hit_trauma")]
+    pub hit_trauma: f32,
+    /// Trauma added whenever the player is hurt.
+    #[serde(default = "default_hurt_trauma")]
+    pub hurt_trauma: f32,
+    /// How quickly the zoom closes the gap to the zoom asked for, per second.
+    #[serde(default = "default_zoom_rate")]
+    pub zoom_rate: f32,
+    /// Furthest the camera zooms out, whatever a pan asks for. Parallax layers are built wide
+    /// enough to cover the view at this zoom.
+    #[serde(default = "default_min_zoom")]
+    pub min_zoom: f32,
+    /// Speed of scripted pans, to their target and back.
+    #[serde(default = "default_pan_speed")]
+    pub pan_speed: f32,
+}
+
+fn default_dead_zone() -> Vector2<f32> {
+    Vector2::new(32.0, 48.0)
+}
+
+fn default_look_ahead_time() -> f32 {
+    0.4
+}
+
+fn default_max_look_ahead() -> f32 {
+    40.0
+}
+
+fn default_smoothing() -> f32 {
+    6.0
+}
+
+fn default_max_shake_offset() -> f32 {
+    8.0
+}
+
+fn default_max_shake_angle() -> f32 {
+    0.05
+}
+
+fn default_trauma_decay() -> f32 {
+    1.5
+}
+
+fn default_hit_trauma() -> f32 {
+    0.3
+}
+
+fn default_hurt_trauma() -> f32 {
+    0.5
+}
+
+fn default_zoom_rate() -> f32 {
+    4.0
+}
+
+fn default_min_zoom() -> f32 {
+    0.5
+}
+
+fn default_pan_speed() -> f32 {
+    240.0
+}
+
+fn default_zoom() -> f32 {
+    1.0
 }
 
 impl Default for CameraConfig {
@@ -18,10 +117,124 @@ impl Default for CameraConfig {
         Self {
             width: 200.0,
             height: 200.0,
+            dead_zone: default_dead_zone(),
+            look_ahead_time: default_look_ahead_time(),
+            max_look_ahead: default_max_look_ahead(),
+            smoothing: default_smoothing(),
+            max_shake_offset: default_max_shake_offset(),
+            max_shake_angle: default_max_shake_angle(),
+            trauma_decay: default_trauma_decay(),
+            hit_trauma: default_hit_trauma(),
+            hurt_trauma: default_hurt_trauma(),
+            zoom_rate: default_zoom_rate(),
+            min_zoom: default_min_zoom(),
+            pan_speed: default_pan_speed(),
+        }
+    }
+}
+
+/// Takes the camera away from the player to look at something, such as a boss or a door.
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+pub struct CameraPan {
+    pub target: Vector2<f32>,
+    /// How long to look at `target` once there before moving on.
+    #[serde(default)]
+    pub hold: f32,
+    #[serde(default = "default_zoom")]
+    pub zoom: f32,
+}
+
+/// Requests for the camera on top of following the player. Anything can push to this; the
+/// `CameraEffectsSystem` plays them out.
+#[derive(Debug)]
+pub struct CameraEffects {
+    /// Zero to one. Shake grows with its square, so small knocks barely register.
+    pub trauma: f32,
+    /// The current zoom, where 2 shows half as much of the level.
+    pub zoom: f32,
+    pans: VecDeque<CameraPan>,
+    /// Where the pans have taken the camera, until it's made its way back to the player.
+    pan_position: Option<Vector2<f32>>,
+    /// How long the camera has been looking at the current pan's target.
+    held: f32,
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        Self {
+            trauma: 0.0,
+            zoom: default_zoom(),
+            pans: VecDeque::new(),
+            pan_position: None,
+            held: 0.0,
         }
     }
 }
 
+impl CameraEffects {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+
+    /// Queues a pan, which starts once the pans before it have finished. The camera returns to
+    /// the player after the last one.
+    pub fn pan_to(&mut self, pan: CameraPan) {
+        self.pans.push_back(pan);
+    }
+
+    /// The zoom to head for, which is the current pan's if there is one.
+    fn wanted_zoom(&self) -> f32 {
+        self.pans.front().map_or(default_zoom(), |pan| pan.zoom)
+    }
+
+    /// Moves the camera up to `distance` along the pans, or back to `follow` once they're done,
+    /// and returns where it should be.
+    fn update_pans(
+        &mut self,
+        follow: Vector2<f32>,
+        distance: f32,
+        delta_seconds: f32,
+    ) -> Vector2<f32> {
+        let current = self.pan_position.unwrap_or(follow);
+        match self.pans.front().copied() {
+            Some(pan) => {
+                let position = move_towards(current, pan.target, distance);
+                if position == pan.target {
+                    self.held += delta_seconds;
+                    if self.held >= pan.hold {
+                        self.pans.pop_front();
+                        self.held = 0.0;
+                    }
+                }
+                self.pan_position = Some(position);
+                position
+            }
+            None => {
+                let position = move_towards(current, follow, distance);
+                self.pan_position = Some(position).filter(|&position| position != follow);
+                position
+            }
+        }
+    }
+}
+
+/// Where the camera is following. None until it first finds the player, when it jumps
+/// straight to them.
+#[derive(Debug, Default)]
+pub struct CameraFollow {
+    pub focus: Option<Vector2<f32>>,
+    pub look_ahead: f32,
+    /// Where following puts the camera, before any effects.
+    pub position: Vector2<f32>,
+    /// Whether the player was off the ground last frame. The camera stops following them
+    /// vertically until they land, then centres on them again.
+    pub airborne: bool,
+}
+
+impl Component for CameraFollow {
+    type Storage = DenseVecStorage<Self>;
+}
+
 pub(crate) fn initialize_camera(world: &mut World) {
     let (screen_height, screen_width) = {
         let config = world.read_resource::<CameraConfig>();
@@ -34,29 +247,206 @@ pub(crate) fn initialize_camera(world: &mut World) {
         .create_entity()
         .with(transform)
         .with(Camera::standard_2d(screen_width, screen_height))
+        .with(CameraFollow::default())
         .build();
+    world.insert(CameraEffects::default());
+}
+
+/// Moves `focus` just far enough along one axis to bring `target` inside a window of `size`
+/// centred on it.
+fn follow1d(focus: f32, target: f32, size: f32) -> f32 {
+    let half = size * 0.5;
+    if target > focus + half {
+        target - half
+    } else if target < focus - half {
+        target + half
+    } else {
+        focus
+    }
 }
 
+/// Keeps a view of `size` centred on `center` within `min` and `max`, centring it if the view
+/// is bigger than the space.
+fn clamp1d(center: f32, size: f32, min: f32, max: f32) -> f32 {
+    let half = size * 0.5;
+    if max - min <= size {
+        (min + max) * 0.5
+    } else {
+        center.max(min + half).min(max - half)
+    }
+}
+
+fn move_towards(from: Vector2<f32>, to: Vector2<f32>, distance: f32) -> Vector2<f32> {
+    let offset = to - from;
+    let length = offset.norm();
+    if length <= distance {
+        to
+    } else {
+        from + offset * (distance / length)
+    }
+}
+
+/// Keeps a view of `size` centred on `position` within the arena.
+fn clamp_to_arena(
+    position: Vector2<f32>,
+    size: Vector2<f32>,
+    bounds: Option<Corners>,
+) -> Vector2<f32> {
+    match bounds {
+        Some(corners) => Vector2::new(
+            clamp1d(position.x, size.x, corners.left(), corners.right()),
+            clamp1d(position.y, size.y, corners.bottom(), corners.top()),
+        ),
+        None => position,
+    }
+}
+
+/// Follows the player with a dead zone and look-ahead, easing towards where it should be and
+/// never showing anything outside the arena.
 pub struct CameraMovementSystem;
+
 impl<'s> System<'s> for CameraMovementSystem {
     type SystemData = (
-        ReadStorage<'s, Camera>,
+        WriteStorage<'s, CameraFollow>,
         ReadStorage<'s, Player>,
-        WriteStorage<'s, Transform>,
+        ReadStorage<'s, PhysicsBox>,
+        ReadStorage<'s, Arena>,
+        ReadStorage<'s, Transform>,
+        Read<'s, CameraEffects>,
+        Read<'s, CameraConfig>,
+        Read<'s, Time>,
     );
-    fn run(&mut self, (cameras, players, mut transforms): Self::SystemData) {
-        let get_camera_translation = || -> Option<Vector3<f32>> {
-            for (_player, player_transform) in (&players, &transforms).join() {
-                let player_vec = player_transform.translation();
-                let camera_vec = Vector3::new(player_vec.x, player_vec.y, 1.0);
-                return Some(camera_vec);
-            }
-            None
+
+    fn run(
+        &mut self,
+        (
+            mut follows,
+            players,
+            physics_boxes,
+            arenas,
+            transforms,
+            effects,
+            config,
+            time,
+        ): Self::SystemData,
+    ) {
+        let player = (&players, &physics_boxes, &transforms)
+            .join()
+            .next()
+            .map(|(player, physics, transform)| {
+                let target = Vector2::new(
+                    transform.translation().x + physics.bbox.corners.x_midpoint(),
+                    transform.translation().y + physics.bbox.corners.y_midpoint(),
+                );
+                let grounded = player.on_ground
+                    || player.state == PlayerState::Climb
+                    || player.state == PlayerState::WallSlide;
+                (target, physics.velocity.x, grounded)
+            });
+        let (target, velocity_x, grounded) = match player {
+            Some(player) => player,
+            None => return,
+        };
+        let bounds = (&arenas).join().next().map(|arena| arena.inverse_bbox.corners);
+        let blend = if config.smoothing > 0.0 {
+            1.0 - (-config.smoothing * time.delta_seconds()).exp()
+        } else {
+            1.0
         };
-        let new_translation = get_camera_translation().unwrap();
 
-        for (_camera, transform) in (&cameras, &mut transforms).join() {
-            transform.set_translation(new_translation);
+        let view = Vector2::new(config.width, config.height) / effects.zoom;
+
+        for follow in (&mut follows).join() {
+            let look_ahead = (velocity_x * config.look_ahead_time)
+                .max(-config.max_look_ahead)
+                .min(config.max_look_ahead);
+            let (focus, blend) = match follow.focus {
+                Some(focus) => {
+                    let focus_y = if !grounded {
+                        // Locked in the air, unless the player is about to leave the view on a
+                        // long fall.
+                        follow1d(focus.y, target.y, view.y)
+                    } else if follow.airborne {
+                        target.y
+                    } else {
+                        follow1d(focus.y, target.y, config.dead_zone.y)
+                    };
+                    follow.look_ahead += (look_ahead - follow.look_ahead) * blend;
+                    let focus_x = follow1d(focus.x, target.x, config.dead_zone.x);
+                    (Vector2::new(focus_x, focus_y), blend)
+                }
+                None => {
+                    follow.look_ahead = look_ahead;
+                    (target, 1.0)
+                }
+            };
+            follow.focus = Some(focus);
+            follow.airborne = !grounded;
+
+            let desired = Vector2::new(focus.x + follow.look_ahead, focus.y);
+            let desired = clamp_to_arena(desired, view, bounds);
+            follow.position += (desired - follow.position) * blend;
+        }
+    }
+}
+
+/// Places the camera where following put it, then plays out the `CameraEffects`: pans, zoom
+/// and shake.
+pub struct CameraEffectsSystem;
+
+impl<'s> System<'s> for CameraEffectsSystem {
+    type SystemData = (
+        ReadStorage<'s, CameraFollow>,
+        ReadStorage<'s, Arena>,
+        WriteStorage<'s, Camera>,
+        WriteStorage<'s, Transform>,
+        Write<'s, CameraEffects>,
+        Read<'s, CameraConfig>,
+        Read<'s, Time>,
+    );
+
+    fn run(
+        &mut self,
+        (follows, arenas, mut cameras, mut transforms, mut effects, config, time): Self::SystemData,
+    ) {
+        let delta_seconds = time.delta_seconds();
+        // Game time stops while paused, when the camera holds still rather than shaking in place.
+        if delta_seconds <= 0.0 {
+            return;
+        }
+        effects.trauma = (effects.trauma - config.trauma_decay * delta_seconds).max(0.0);
+
+        let previous_zoom = effects.zoom;
+        let wanted_zoom = effects.wanted_zoom().max(config.min_zoom);
+        effects.zoom += (wanted_zoom - effects.zoom)
+            * (1.0 - (-config.zoom_rate * delta_seconds).exp());
+        if (wanted_zoom - effects.zoom).abs() < 0.001 {
+            effects.zoom = wanted_zoom;
+        }
+        let view = Vector2::new(config.width, config.height) / effects.zoom;
+
+        let bounds = (&arenas).join().next().map(|arena| arena.inverse_bbox.corners);
+        let shake = effects.trauma * effects.trauma;
+        let mut rng = rand::thread_rng();
+
+        for (follow, camera, transform) in (&follows, &mut cameras, &mut transforms).join() {
+            if follow.focus.is_none() {
+                continue;
+            }
+            let distance = config.pan_speed * delta_seconds;
+            let position = effects.update_pans(follow.position, distance, delta_seconds);
+            let position = clamp_to_arena(position, view, bounds);
+            let offset = Vector2::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0))
+                * (shake * config.max_shake_offset);
+            transform.set_translation_xyz(
+                position.x + offset.x,
+                position.y + offset.y,
+                transform.translation().z,
+            );
+            transform.set_rotation_2d(shake * config.max_shake_angle * rng.gen_range(-1.0, 1.0));
+            if effects.zoom != previous_zoom {
+                *camera = Camera::standard_2d(view.x, view.y);
+            }
         }
     }
 }
@@ -0,0 +1,161 @@
+use amethyst::{
+    assets::Handle,
+    core::{math::Vector2, Transform},
+    ecs::{
+        Component, DenseVecStorage, Entity, Join, Read, ReadStorage, System, World, WriteStorage,
+    },
+    prelude::*,
+    renderer::{SpriteRender, SpriteSheet},
+};
+use serde::{Deserialize, Serialize};
+
+use super::broadphase::SpatialHash;
+use super::physics::{BoundingBox2D, CollisionLayers, PhysicsBox};
+use super::player::Player;
+use super::player_state::PlayerState;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum ClimbableKind {
+    /// Climbed straight up and down, and off the top onto whatever it leads to.
+    Ladder,
+    /// Hangs from its top and can be swung on.
+    Rope,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClimbablePosition {
+    pub kind: ClimbableKind,
+    /// Bottom left corner in world coordinates.
+    pub position: Vector2<f32>,
+    pub width: f32,
+    pub height: f32,
+    #[serde(default)]
+    pub sprite_num: usize,
+    #[serde(default = "CollisionLayers::climbable")]
+    pub layers: CollisionLayers,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ClimbablesConfig {
+    pub elements: Vec<ClimbablePosition>,
+}
+
+#[derive(Debug)]
+pub struct Climbable {
+    pub kind: ClimbableKind,
+}
+
+impl Component for Climbable {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The climbable a player is touching, relative to the player.
+#[derive(Debug, Copy, Clone)]
+pub struct ClimbContact {
+    pub entity: Entity,
+    pub kind: ClimbableKind,
+    /// From the player's middle to the climbable's middle.
+    pub offset_x: f32,
+    /// From the player's feet up to the top of the climbable.
+    pub to_top: f32,
+    /// From the player's middle up to the top of the climbable, which ropes swing from.
+    pub length: f32,
+}
+
+/// Climbable sprites are repeated this far apart to cover the climbable's height.
+const SEGMENT_HEIGHT: f32 = 16.0;
+
+const CLIMBABLE_PLANE: f32 = -0.001;
+
+pub fn initialize_climbables(world: &mut World, sprite_sheet: Handle<SpriteSheet>) {
+    let elements = world.read_resource::<ClimbablesConfig>().elements.clone();
+
+    for elem in elements {
+        let center_x = elem.position.x + elem.width / 2.0;
+        let segments = (elem.height / SEGMENT_HEIGHT).ceil() as usize;
+        for segment in 0..segments {
+            let mut transform = Transform::default();
+            transform.set_translation_xyz(
+                center_x,
+                elem.position.y + (segment as f32 + 0.5) * SEGMENT_HEIGHT,
+                CLIMBABLE_PLANE,
+            );
+            world
+                .create_entity()
+                .with(SpriteRender {
+                    sprite_sheet: sprite_sheet.clone(),
+                    sprite_number: elem.sprite_num,
+                })
+                .with(transform)
+                .build();
+        }
+
+        let bbox = BoundingBox2D::new(Vector2::new(0.0, 0.0), elem.width, elem.height);
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(elem.position.x, elem.position.y, CLIMBABLE_PLANE);
+        world
+            .create_entity()
+            .with(Climbable { kind: elem.kind })
+            .with(PhysicsBox::new(bbox).with_layers(elem.layers))
+            .with(transform)
+            .build();
+    }
+}
+
+/// Finds the climbable each player is touching. A climbing player keeps hold of their
+/// climbable while level with it, so swinging on a rope doesn't let go of it.
+#[derive(SystemDesc)]
+pub struct ClimbableSystem;
+
+impl<'s> System<'s> for ClimbableSystem {
+    type SystemData = (
+        WriteStorage<'s, Player>,
+        ReadStorage<'s, Climbable>,
+        ReadStorage<'s, PhysicsBox>,
+        ReadStorage<'s, Transform>,
+        Read<'s, SpatialHash>,
+    );
+
+    fn run(
+        &mut self,
+        (mut players, climbables, physics_boxes, transforms, hash): Self::SystemData,
+    ) {
+        let world_box = |entity: Entity| {
+            let physics = physics_boxes.get(entity)?;
+            let transform = transforms.get(entity)?;
+            let position = Vector2::new(transform.translation().x, transform.translation().y);
+            Some(physics.bbox.translate(position))
+        };
+
+        for (player, physics, transform) in (&mut players, &physics_boxes, &transforms).join() {
+            let position = Vector2::new(transform.translation().x, transform.translation().y);
+            let body = physics.bbox.translate(position);
+
+            let held = player
+                .climbable
+                .filter(|_| player.state == PlayerState::Climb)
+                .and_then(|contact| Some((contact.entity, world_box(contact.entity)?)))
+                .filter(|(_, bbox)| {
+                    body.corners.bottom() < bbox.corners.top()
+                        && body.corners.top() > bbox.corners.bottom()
+                });
+            let touching = || {
+                hash.query_aabb(&body)
+                    .into_iter()
+                    .filter(|collider| physics.layers.interacts_with(&collider.layers))
+                    .find(|collider| climbables.contains(collider.entity))
+                    .map(|collider| (collider.entity, collider.bbox))
+            };
+
+            player.climbable = held.or_else(touching).and_then(|(entity, bbox)| {
+                Some(ClimbContact {
+                    entity,
+                    kind: climbables.get(entity)?.kind,
+                    offset_x: bbox.corners.x_midpoint() - body.corners.x_midpoint(),
+                    to_top: bbox.corners.top() - body.corners.bottom(),
+                    length: bbox.corners.top() - body.corners.y_midpoint(),
+                })
+            });
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use amethyst::{
+    assets::Handle,
+    core::{math::Vector2, Transform},
+    ecs::{
+        Component, DenseVecStorage, Entities, Entity, Join, LazyUpdate, Read, ReadStorage, System,
+        World, Write, WriteStorage,
+    },
+    renderer::{SpriteRender, SpriteSheet},
+    shrev::EventChannel,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::game_data::{FixedTimestep, HitStop};
+use super::broadphase::SpatialHash;
+use super::camera::{CameraConfig, CameraEffects};
+use super::enemy::Enemy;
+use super::interpolation::SimulationPosition;
+use super::physics::{accelerate1d, BoundingBox2D, CollisionLayers, Layer, PhysicsBox};
+use super::player::Player;
+
+/// Downward acceleration of a projectile with a gravity scale of 1.
+pub const PROJECTILE_GRAVITY: f32 = 500.0;
+
+/// Something hit something else. Targets react to these rather than to overlaps directly.
+#[derive(Debug, Copy, Clone)]
+pub struct HitEvent {
+    /// Whoever is responsible for the hit, such as the thrower of a star.
+    pub attacker: Entity,
+    pub target: Entity,
+    pub damage: u32,
+    /// Where the hit came from, for knocking the target away from it.
+    pub source: Vector2<f32>,
+    /// Overrides the target's own knockback. Already flipped to point away from the attacker.
+    pub knockback: Option<Vector2<f32>>,
+}
+
+/// Sprite sheet for projectiles.
+pub struct ProjectileSprites {
+    pub sprite_sheet: Handle<SpriteSheet>,
+}
+
+pub fn initialize_combat(world: &mut World, sprite_sheet: Handle<SpriteSheet>) {
+    world.insert(ProjectileSprites { sprite_sheet });
+}
+
+#[derive(Debug)]
+pub struct Projectile {
+    pub owner: Entity,
+    /// Multiplies PROJECTILE_GRAVITY. Zero flies straight.
+    pub gravity_scale: f32,
+    /// Seconds left before the projectile disappears.
+    pub lifetime: f32,
+    /// How many more targets the projectile passes through before it's used up.
+    pub pierce: u32,
+    pub damage: u32,
+    /// Targets already hit, so piercing projectiles hit each only once.
+    pub hit: Vec<Entity>,
+}
+
+impl Component for Projectile {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Everything needed to launch a projectile.
+#[derive(Debug, Clone)]
+pub struct ProjectileSpec {
+    pub sprite_number: usize,
+    pub bbox: BoundingBox2D,
+    pub velocity: Vector2<f32>,
+    pub gravity_scale: f32,
+    pub lifetime: f32,
+    pub pierce: u32,
+    pub damage: u32,
+    pub layers: CollisionLayers,
+}
+
+/// Queues a projectile to be created at the end of the step, centred on `position`.
+pub fn spawn_projectile(
+    entities: &Entities,
+    lazy: &LazyUpdate,
+    sprites: &ProjectileSprites,
+    owner: Entity,
+    position: Vector2<f32>,
+    spec: &ProjectileSpec,
+) {
+    let mut physics = PhysicsBox::new(spec.bbox).with_layers(spec.layers);
+    physics.velocity = spec.velocity;
+    let mut transform = Transform::default();
+    transform.set_translation_xyz(position.x, position.y, 0.0);
+
+    lazy.create_entity(entities)
+        .with(SpriteRender {
+            sprite_sheet: sprites.sprite_sheet.clone(),
+            sprite_number: spec.sprite_number,
+        })
+        .with(Projectile {
+            owner,
+            gravity_scale: spec.gravity_scale,
+            lifetime: spec.lifetime,
+            pierce: spec.pierce,
+            damage: spec.damage,
+            hit: Vec::new(),
+        })
+        .with(physics)
+        .with(SimulationPosition::new(*transform.translation()))
+        .with(transform)
+        .build();
+}
+
+/// Ages projectiles, applies their gravity and sends a HitEvent for each body they touch.
+/// Projectiles are destroyed when they hit the ground, run out of targets or expire.
+#[derive(SystemDesc)]
+pub struct ProjectileSystem;
+
+impl<'s> System<'s> for ProjectileSystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, Projectile>,
+        WriteStorage<'s, PhysicsBox>,
+        ReadStorage<'s, Transform>,
+        Read<'s, SpatialHash>,
+        Write<'s, EventChannel<HitEvent>>,
+        Read<'s, FixedTimestep>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, mut projectiles, mut physics_boxes, transforms, hash, mut hits, timestep): Self::SystemData,
+    ) {
+        let time_step = timestep.step;
+        for (entity, projectile, physics, transform) in
+            (&entities, &mut projectiles, &mut physics_boxes, &transforms).join()
+        {
+            projectile.lifetime -= time_step;
+            if projectile.lifetime <= 0.0 || physics.contacts.any() {
+                entities.delete(entity).ok();
+                continue;
+            }
+            physics.velocity.y = accelerate1d(
+                physics.velocity.y,
+                -PROJECTILE_GRAVITY * projectile.gravity_scale,
+                time_step,
+            );
+
+            let position = Vector2::new(transform.translation().x, transform.translation().y);
+            let body = physics.bbox.translate(position);
+            for collider in hash.query_aabb(&body) {
+                if collider.is_static()
+                    || collider.entity == entity
+                    || collider.entity == projectile.owner
+                    || collider.layers.membership.contains(Layer::Projectile)
+                    || !physics.layers.interacts_with(&collider.layers)
+                    || projectile.hit.contains(&collider.entity)
+                {
+                    continue;
+                }
+                projectile.hit.push(collider.entity);
+                hits.single_write(HitEvent {
+                    attacker: projectile.owner,
+                    target: collider.entity,
+                    damage: projectile.damage,
+                    source: Vector2::new(body.corners.x_midpoint(), body.corners.y_midpoint()),
+                    knockback: None,
+                });
+                if projectile.pierce == 0 {
+                    entities.delete(entity).ok();
+                    break;
+                }
+                projectile.pierce -= 1;
+            }
+        }
+    }
+}
+
+/// A box relative to an entity's PhysicsBox, as it is when facing right. `offset` is from the
+/// bottom left corner of the PhysicsBox. Facing left mirrors it about the PhysicsBox's middle.
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+pub struct BoxShape {
+    pub offset: Vector2<f32>,
+    pub size: Vector2<f32>,
+}
+
+impl BoxShape {
+    pub fn place(&self, body: &BoundingBox2D, facing: f32) -> BoundingBox2D {
+        let x = if facing < 0.0 {
+            body.corners.right() - self.offset.x - self.size.x
+        } else {
+            body.corners.left() + self.offset.x
+        };
+        let y = body.corners.bottom() + self.offset.y;
+        BoundingBox2D::new(Vector2::new(x, y), self.size.x, self.size.y)
+    }
+}
+
+/// Where an entity can be hit. Entities without one can't be hurt by melee attacks.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Hurtbox {
+    /// None covers the whole PhysicsBox, following it as it changes shape.
+    pub shape: Option<BoxShape>,
+}
+
+impl Component for Hurtbox {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// One hitbox of an attack and what it does to whatever it hits.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HitboxShape {
+    pub shape: BoxShape,
+    /// The first and last frames of the attack the hitbox is out for, counted in
+    /// `AttackData::frame_time`s since it started.
+    pub frames: (u32, u32),
+    pub damage: u32,
+    /// Applied to the target, as if the attacker is facing right.
+    pub knockback: Vector2<f32>,
+    /// How long the whole simulation freezes when this lands.
+    #[serde(default)]
+    pub hit_stop: f32,
+}
+
+/// Frame data for an attack. The frames are counted off the attack's own timer, and the
+/// attack's animation is keyed on the same frames and shown from `ActiveAttack::frame`, so
+/// the hitboxes are out on exactly the frames drawn with the blade out.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AttackData {
+    /// Seconds per frame.
+    pub frame_time: f32,
+    pub frame_count: u32,
+    pub hitboxes: Vec<HitboxShape>,
+}
+
+impl AttackData {
+    pub fn duration(&self) -> f32 {
+        self.frame_time * self.frame_count as f32
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ActiveAttack {
+    pub data: AttackData,
+    pub elapsed: f32,
+    /// Targets already hit, so each is hit once per attack.
+    pub hit: Vec<Entity>,
+}
+
+impl ActiveAttack {
+    pub fn frame(&self) -> u32 {
+        (self.elapsed / self.data.frame_time.max(std::f32::EPSILON)) as u32
+    }
+}
+
+/// Something which makes melee attacks.
+#[derive(Debug, Default)]
+pub struct Attacker {
+    pub attack: Option<ActiveAttack>,
+}
+
+impl Attacker {
+    pub fn start(&mut self, data: &AttackData) {
+        self.attack = Some(ActiveAttack {
+            data: data.clone(),
+            elapsed: 0.0,
+            hit: Vec::new(),
+        });
+    }
+}
+
+impl Component for Attacker {
+    type Storage = DenseVecStorage<Self>;
+}
+
+fn facing(entity: Entity, players: &ReadStorage<Player>, enemies: &ReadStorage<Enemy>) -> f32 {
+    players
+        .get(entity)
+        .map(|player| player.facing)
+        .or_else(|| enemies.get(entity).map(|enemy| enemy.facing))
+        .unwrap_or(1.0)
+}
+
+/// Advances attacks frame by frame and sends a HitEvent the first time each active hitbox of
+/// an attack overlaps another entity's hurtbox.
+#[derive(SystemDesc)]
+pub struct HitResolutionSystem;
+
+impl<'s> System<'s> for HitResolutionSystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, Attacker>,
+        ReadStorage<'s, Hurtbox>,
+        ReadStorage<'s, PhysicsBox>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, Player>,
+        ReadStorage<'s, Enemy>,
+        Write<'s, EventChannel<HitEvent>>,
+        Write<'s, HitStop>,
+        Write<'s, CameraEffects>,
+        Read<'s, CameraConfig>,
+        Read<'s, FixedTimestep>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut attackers,
+            hurtboxes,
+            physics_boxes,
+            transforms,
+            players,
+            enemies,
+            mut hits,
+            mut hit_stop,
+            mut camera_effects,
+            camera_config,
+            timestep,
+        ): Self::SystemData,
+    ) {
+        let world_box = |physics: &PhysicsBox, transform: &Transform| {
+            let position = Vector2::new(transform.translation().x, transform.translation().y);
+            physics.bbox.translate(position)
+        };
+        let targets: Vec<(Entity, BoundingBox2D, CollisionLayers)> =
+            (&entities, &hurtboxes, &physics_boxes, &transforms)
+                .join()
+                .map(|(entity, hurtbox, physics, transform)| {
+                    let body = world_box(physics, transform);
+                    let area = match hurtbox.shape {
+                        Some(shape) => shape.place(&body, facing(entity, &players, &enemies)),
+                        None => body,
+                    };
+                    (entity, area, physics.layers)
+                })
+                .collect();
+
+        for (entity, attacker, physics, transform) in
+            (&entities, &mut attackers, &physics_boxes, &transforms).join()
+        {
+            let finished = match attacker.attack.as_mut() {
+                Some(attack) => {
+                    let body = world_box(physics, transform);
+                    let direction = facing(entity, &players, &enemies);
+                    let frame = attack.frame();
+                    for hitbox in &attack.data.hitboxes {
+                        if frame < hitbox.frames.0 || frame > hitbox.frames.1 {
+                            continue;
+                        }
+                        let area = hitbox.shape.place(&body, direction);
+                        for (target, target_area, target_layers) in &targets {
+                            if *target == entity
+                                || attack.hit.contains(target)
+                                || !physics.layers.interacts_with(target_layers)
+                                || !area.intersects(target_area)
+                            {
+                                continue;
+                            }
+                            attack.hit.push(*target);
+                            hits.single_write(HitEvent {
+                                attacker: entity,
+                                target: *target,
+                                damage: hitbox.damage,
+                                source: Vector2::new(area.corners.x_midpoint(), area.corners.y_midpoint()),
+                                knockback: Some(Vector2::new(
+                                    direction * hitbox.knockback.x,
+                                    hitbox.knockback.y,
+                                )),
+                            });
+                            hit_stop.freeze(hitbox.hit_stop);
+                            camera_effects.add_trauma(camera_config.hit_trauma);
+                        }
+                    }
+                    attack.elapsed += timestep.step;
+                    attack.elapsed >= attack.data.duration()
+                }
+                None => false,
+            };
+            if finished {
+                attacker.attack = None;
+            }
+        }
+    }
+}
@@ -1,28 +1,449 @@
 use amethyst::{
     assets::Handle,
-    core::{math::Vector2, Time, Transform},
-    ecs::{Component, DenseVecStorage, Join, Read, ReadStorage, System, World, WriteStorage},
-    input::{InputHandler, StringBindings},
+    core::{math::Vector2, Transform},
+    ecs::{
+        Component, DenseVecStorage, Entities, Join, LazyUpdate, Read, ReadStorage, System,
+        SystemData, World, WriteStorage,
+    },
     prelude::*,
-    renderer::{SpriteRender, SpriteSheet},
+    renderer::{resources::Tint, palette::Srgba, SpriteRender, SpriteSheet},
+    shrev::{EventChannel, ReaderId},
 };
 use serde::{Deserialize, Serialize};
 
-use crate::components::physics::{
-    accelerate1d, decelerate1d, BoundingBox2D, Corners, MINIMUM_CLIP,
+use crate::game_data::FixedTimestep;
+use crate::geometry::Corners;
+use super::broadphase::SpatialHash;
+use super::combat::{spawn_projectile, HitEvent, Hurtbox, ProjectileSpec, ProjectileSprites};
+use super::interpolation::SimulationPosition;
+use super::physics::{
+    accelerate1d, BoundingBox2D, CollisionLayers, Layer, PhysicsBox, MINIMUM_CLIP,
 };
+use super::player::Player;
 
-
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-enum EnemyKind {
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EnemyKind {
     Grunt
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct EnemyConfig {
-    location: Vector2<f32>,
-    kind: EnemyKind,
-    max_speed: f32,
-    accel: f32,
-    fall_accel: f32,
+    pub location: Vector2<f32>,
+    pub kind: EnemyKind,
+    #[serde(default = "default_max_speed")]
+    pub max_speed: f32,
+    #[serde(default = "default_accel")]
+    pub accel: f32,
+    #[serde(default = "default_fall_accel")]
+    pub fall_accel: f32,
+    #[serde(default = "default_max_fall_speed")]
+    pub max_fall_speed: f32,
+    /// How long a hit knocks the enemy back for, before it walks again.
+    #[serde(default = "default_knockback_time")]
+    pub knockback_time: f32,
+    /// How far ahead the enemy can see the player.
+    #[serde(default = "default_sight_range")]
+    pub sight_range: f32,
+    #[serde(default)]
+    pub sprite_num: usize,
+    #[serde(default = "default_health")]
+    pub health: u32,
+    /// Awarded to the player for defeating the enemy.
+    #[serde(default = "default_score")]
+    pub score: usize,
+    /// Enemies with a shot fire at the player while chasing them.
+    #[serde(default)]
+    pub shot: Option<EnemyShotConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EnemyShotConfig {
+    pub speed: f32,
+    /// Seconds between shots.
+    pub cooldown: f32,
+    #[serde(default)]
+    pub gravity_scale: f32,
+    #[serde(default = "default_shot_lifetime")]
+    pub lifetime: f32,
+}
+
+fn default_max_speed() -> f32 {
+    20.0
+}
+
+fn default_accel() -> f32 {
+    30.0
+}
+
+fn default_fall_accel() -> f32 {
+    500.0
+}
+
+fn default_max_fall_speed() -> f32 {
+    120.0
+}
+
+fn default_knockback_time() -> f32 {
+    0.3
+}
+
+fn default_sight_range() -> f32 {
+    96.0
+}
+
+fn default_health() -> u32 {
+    1
+}
+
+fn default_score() -> usize {
+    100
+}
+
+fn default_shot_lifetime() -> f32 {
+    2.0
+}
+
+impl EnemyConfig {
+    pub fn new(kind: EnemyKind, location: Vector2<f32>) -> Self {
+        Self {
+            location,
+            kind,
+            max_speed: default_max_speed(),
+            accel: default_accel(),
+            fall_accel: default_fall_accel(),
+            max_fall_speed: default_max_fall_speed(),
+            knockback_time: default_knockback_time(),
+            sight_range: default_sight_range(),
+            sprite_num: 0,
+            health: default_health(),
+            score: default_score(),
+            shot: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EnemiesConfig {
+    pub elements: Vec<EnemyConfig>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EnemyBehavior {
+    Patrol,
+    Chase,
+}
+
+/// Chasing Grunts run this much faster than they patrol.
+const CHASE_SPEED_MULTIPLIER: f32 = 1.5;
+/// The player must be within this height of the enemy to be seen.
+const SIGHT_HEIGHT: f32 = 24.0;
+const SHOT_SPRITE: usize = 1;
+
+lazy_static! {
+    static ref SHOT_BBOX: BoundingBox2D = {
+        BoundingBox2D {
+            corners: Corners {
+                bottom_left: Vector2::new(-2.0, -2.0),
+                top_right: Vector2::new(2.0, 2.0),
+            },
+        }
+    };
+}
+
+#[derive(Debug)]
+pub struct Enemy {
+    pub config: EnemyConfig,
+    pub behavior: EnemyBehavior,
+    /// 1.0 facing right, -1.0 facing left.
+    pub facing: f32,
+    pub health: u32,
+    pub shot_timer: f32,
+    /// Counts down after being knocked back. The enemy doesn't walk until it runs out.
+    pub knockback_timer: f32,
+}
+
+impl Enemy {
+    pub fn new(config: EnemyConfig) -> Self {
+        Self {
+            config,
+            behavior: EnemyBehavior::Patrol,
+            facing: -1.0,
+            health: config.health,
+            shot_timer: 0.0,
+            knockback_timer: 0.0,
+        }
+    }
+
+    pub fn turn(&mut self) {
+        self.facing = -self.facing;
+    }
+
+    pub fn fall(&self, physics: &mut PhysicsBox, time_step: f32) {
+        physics.velocity.y = accelerate1d(physics.velocity.y, -self.config.fall_accel, time_step)
+            .max(-self.config.max_fall_speed);
+    }
+
+    /// Accelerate towards `max_speed` in the direction being faced.
+    pub fn walk(&self, physics: &mut PhysicsBox, max_speed: f32, time_step: f32) {
+        physics.velocity.x =
+            accelerate1d(physics.velocity.x, self.facing * self.config.accel, time_step)
+                .max(-max_speed)
+                .min(max_speed);
+    }
+}
+
+impl Component for Enemy {
+    type Storage = DenseVecStorage<Self>;
+}
+
+lazy_static! {
+    static ref GRUNT_BBOX: BoundingBox2D = {
+        BoundingBox2D {
+            corners: Corners {
+                bottom_left: Vector2::new(4.0, 0.0),
+                top_right: Vector2::new(12.0, 15.5),
+            },
+        }
+    };
+}
+
+pub fn initialize_enemies(world: &mut World, sprite_sheet: Handle<SpriteSheet>) {
+    let elements: Vec<EnemyConfig> = {
+        let config = world.read_resource::<EnemiesConfig>();
+        config.elements.clone()
+    };
+
+    for elem in elements {
+        let sprite_render = SpriteRender {
+            sprite_sheet: sprite_sheet.clone(),
+            sprite_number: elem.sprite_num,
+        };
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(elem.location.x, elem.location.y, 0.0);
+
+        world
+            .create_entity()
+            .with(sprite_render)
+            // Grunts share the player's sprites for now.
+            .with(Tint(Srgba::new(1.0, 0.4, 0.4, 1.0)))
+            .with(Enemy::new(elem))
+            .with(Hurtbox::default())
+            .with(PhysicsBox::new(*GRUNT_BBOX).with_layers(CollisionLayers::enemy()))
+            .with(SimulationPosition::new(*transform.translation()))
+            .with(transform)
+            .build();
+    }
+}
+
+/// Drives enemies. Grunts patrol back and forth, turning at walls and ledges, and chase the
+/// player when they can see them.
+#[derive(SystemDesc)]
+pub struct EnemySystem;
+
+impl<'s> System<'s> for EnemySystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, Enemy>,
+        WriteStorage<'s, PhysicsBox>,
+        ReadStorage<'s, Player>,
+        ReadStorage<'s, Transform>,
+        Read<'s, SpatialHash>,
+        Option<Read<'s, ProjectileSprites>>,
+        Read<'s, LazyUpdate>,
+        Read<'s, FixedTimestep>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut enemies,
+            mut physics_boxes,
+            players,
+            transforms,
+            hash,
+            sprites,
+            lazy,
+            timestep,
+        ): Self::SystemData,
+    ) {
+        let time_step = timestep.step;
+        let targets: Vec<BoundingBox2D> = (&players, &physics_boxes, &transforms)
+            .join()
+            .map(|(_, physics, transform)| {
+                let position = Vector2::new(transform.translation().x, transform.translation().y);
+                physics.bbox.translate(position)
+            })
+            .collect();
+
+        for (entity, enemy, physics, transform) in
+            (&entities, &mut enemies, &mut physics_boxes, &transforms).join()
+        {
+            enemy.shot_timer = (enemy.shot_timer - time_step).max(0.0);
+            enemy.knockback_timer = (enemy.knockback_timer - time_step).max(0.0);
+            let position = Vector2::new(transform.translation().x, transform.translation().y);
+            let body = physics.bbox.translate(position);
+            let box_below = body.translate(Vector2::new(0.0, -0.5));
+            let on_ground = physics.contacts.down
+                || hash.query_statics(&box_below, &physics.layers).iter().any(|solid| {
+                    (solid.slope.is_some() || solid.bbox.intersects(&box_below))
+                        && solid.supports(&body, false)
+                });
+
+            let seen = targets.iter().find(|target| can_see(&hash, enemy, &body, target));
+            let target = seen.map(|target| target.corners.x_midpoint());
+            enemy.behavior = match target {
+                Some(_) => EnemyBehavior::Chase,
+                None => EnemyBehavior::Patrol,
+            };
+
+            if let (Some(shot), Some(seen), Some(sprites)) = (enemy.config.shot, seen, sprites.as_ref()) {
+                if enemy.shot_timer <= 0.0 {
+                    enemy.shot_timer = shot.cooldown;
+                    let start = Vector2::new(body.corners.x_midpoint(), body.corners.y_midpoint());
+                    let aim = Vector2::new(seen.corners.x_midpoint(), seen.corners.y_midpoint()) - start;
+                    let direction = if aim.norm() > MINIMUM_CLIP {
+                        aim.normalize()
+                    } else {
+                        Vector2::new(enemy.facing, 0.0)
+                    };
+                    let spec = ProjectileSpec {
+                        sprite_number: SHOT_SPRITE,
+                        bbox: *SHOT_BBOX,
+                        velocity: direction * shot.speed,
+                        gravity_scale: shot.gravity_scale,
+                        lifetime: shot.lifetime,
+                        pierce: 0,
+                        damage: 1,
+                        layers: CollisionLayers::enemy_projectile(),
+                    };
+                    spawn_projectile(&entities, &lazy, sprites, entity, start, &spec);
+                }
+            }
+
+            if !on_ground {
+                enemy.fall(physics, time_step);
+                continue;
+            }
+            // Let the knockback carry the enemy rather than walking straight out of it.
+            if enemy.knockback_timer > 0.0 {
+                continue;
+            }
+
+            let blocked = (enemy.facing > 0.0 && physics.contacts.right)
+                || (enemy.facing < 0.0 && physics.contacts.left);
+            let ledge_ahead = !has_ground_ahead(&hash, &physics.layers, enemy.facing, &body);
+            match (enemy.behavior, target) {
+                (EnemyBehavior::Chase, Some(target_x)) => {
+                    let offset = target_x - body.corners.x_midpoint();
+                    if offset.abs() > MINIMUM_CLIP {
+                        enemy.facing = offset.signum();
+                    }
+                    // Chasing Grunts wait at the edge rather than running off it.
+                    if ledge_ahead || blocked {
+                        physics.velocity.x = 0.0;
+                    } else {
+                        let max_speed = enemy.config.max_speed * CHASE_SPEED_MULTIPLIER;
+                        enemy.walk(physics, max_speed, time_step);
+                    }
+                }
+                _ => {
+                    if ledge_ahead || blocked {
+                        enemy.turn();
+                        physics.velocity.x = 0.0;
+                    }
+                    enemy.walk(physics, enemy.config.max_speed, time_step);
+                }
+            }
+        }
+    }
+}
+
+/// Whether there is ground just beyond the front foot of `body`.
+fn has_ground_ahead(
+    hash: &SpatialHash,
+    layers: &CollisionLayers,
+    facing: f32,
+    body: &BoundingBox2D,
+) -> bool {
+    let foot_x = if facing > 0.0 {
+        body.corners.right() + 1.0
+    } else {
+        body.corners.left() - 1.0
+    };
+    let probe = Vector2::new(foot_x, body.corners.bottom() - 1.0);
+    hash.query_point(probe)
+        .iter()
+        .any(|collider| collider.is_static() && layers.interacts_with(&collider.layers))
+}
+
+/// Whether `enemy` is facing `target`, it's within range and no ground is in the way.
+fn can_see(
+    hash: &SpatialHash,
+    enemy: &Enemy,
+    body: &BoundingBox2D,
+    target: &BoundingBox2D,
+) -> bool {
+    let eye = Vector2::new(body.corners.x_midpoint(), body.corners.top() - 2.0);
+    let target_point = Vector2::new(target.corners.x_midpoint(), target.corners.y_midpoint());
+    let offset = target_point - eye;
+    let facing_target = offset.x.signum() == enemy.facing || enemy.behavior == EnemyBehavior::Chase;
+    if !facing_target
+        || offset.x.abs() > enemy.config.sight_range
+        || (target.corners.bottom() - body.corners.bottom()).abs() > SIGHT_HEIGHT
+    {
+        return false;
+    }
+    !hash.cast_segment([eye, target_point]).iter().any(|(_, collider)| {
+        collider.is_static()
+            && !collider.one_way
+            && collider.layers.membership.contains(Layer::Ground)
+    })
+}
+
+/// Applies hits to enemies, defeating them when their health runs out.
+#[derive(Default)]
+pub struct EnemyHitSystem {
+    reader: Option<ReaderId<HitEvent>>,
+}
+
+impl<'s> System<'s> for EnemyHitSystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, Enemy>,
+        WriteStorage<'s, PhysicsBox>,
+        WriteStorage<'s, Player>,
+        Read<'s, EventChannel<HitEvent>>,
+    );
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.reader = Some(world.fetch_mut::<EventChannel<HitEvent>>().register_reader());
+    }
+
+    fn run(
+        &mut self,
+        (entities, mut enemies, mut physics_boxes, mut players, hits): Self::SystemData,
+    ) {
+        let reader = self.reader.as_mut().expect("EnemyHitSystem::setup was not called");
+        for hit in hits.read(reader) {
+            let enemy = match enemies.get_mut(hit.target) {
+                Some(enemy) if enemy.health > 0 => enemy,
+                _ => continue,
+            };
+            enemy.health = enemy.health.saturating_sub(hit.damage);
+            if let Some(knockback) = hit.knockback {
+                if let Some(physics) = physics_boxes.get_mut(hit.target) {
+                    physics.velocity = knockback;
+                    enemy.knockback_timer = enemy.config.knockback_time;
+                }
+            }
+            if enemy.health == 0 {
+                if let Some(player) = players.get_mut(hit.attacker) {
+                    player.score += enemy.config.score;
+                }
+                entities.delete(hit.target).ok();
+            }
+        }
+    }
 }
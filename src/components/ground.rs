@@ -2,22 +2,95 @@ use amethyst::{
     assets::Handle,
     core::{math::Vector2, Transform},
     ecs::{
-        Join, ReadStorage, System, SystemData, World, WriteStorage,
+        Join, Read, ReadStorage, System, SystemData, World, WriteStorage,
     },
     prelude::*,
     renderer::{SpriteRender, SpriteSheet},
 };
 use serde::{Deserialize, Serialize};
-use super::physics::{BoundingBox2D, PhysicsBox};
+use super::broadphase::SpatialHash;
+use super::physics::{BoundingBox2D, CollisionLayers, OneWayPlatform, PhysicsBox, Slope};
 use super::player::Player;
 
+pub const TILE_WIDTH: f32 = 16.0;
+pub const TILE_HEIGHT: f32 = 24.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum SlopeAngle {
+    /// 45 degrees, rising a full tile width across the tile.
+    Steep,
+    /// 22.5 degrees.
+    Shallow,
+}
+
+impl SlopeAngle {
+    /// How far the slope climbs across a tile `width` wide.
+    pub fn rise(&self, width: f32) -> f32 {
+        let degrees: f32 = match self {
+            SlopeAngle::Steep => 45.0,
+            SlopeAngle::Shallow => 22.5,
+        };
+        width * degrees.to_radians().tan()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum TileShape {
+    Solid,
+    /// Drawn but without a collider.
+    Decoration,
+    /// Rises from left to right. `base` is the height of the low end above the tile's bottom.
+    SlopeUp { angle: SlopeAngle, #[serde(default)] base: f32 },
+    /// Falls from left to right. `base` is the height of the low end above the tile's bottom.
+    SlopeDown { angle: SlopeAngle, #[serde(default)] base: f32 },
+}
+
+impl Default for TileShape {
+    fn default() -> Self {
+        TileShape::Solid
+    }
+}
+
+impl TileShape {
+    /// The slope collider for a tile of `size` with its bottom left corner at `pos`.
+    pub fn slope(&self, pos: Vector2<f32>, size: Vector2<f32>) -> Option<Slope> {
+        let (low, high, rising) = match *self {
+            TileShape::Solid | TileShape::Decoration => return None,
+            TileShape::SlopeUp { angle, base } => (base, base + angle.rise(size.x), true),
+            TileShape::SlopeDown { angle, base } => (base, base + angle.rise(size.x), false),
+        };
+        let (left, right) = if rising { (low, high) } else { (high, low) };
+        Some(Slope {
+            surface: [
+                Vector2::new(pos.x, pos.y + left),
+                Vector2::new(pos.x + size.x, pos.y + right),
+            ],
+            bottom: pos.y,
+        })
+    }
+}
+
+pub fn default_tile_size() -> Vector2<f32> {
+    Vector2::new(TILE_WIDTH, TILE_HEIGHT)
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GroundPosition {
     pub sprite_num: usize,
     pub pos: Vector2<f32>,
+    /// Tiles from a tilemap take the map's tile size.
+    #[serde(default = "default_tile_size")]
+    pub size: Vector2<f32>,
+    #[serde(default = "CollisionLayers::ground")]
+    pub layers: CollisionLayers,
+    /// Jump-through platform which is only solid from above.
+    #[serde(default)]
+    pub one_way: bool,
+    #[serde(default)]
+    pub shape: TileShape,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GroundConfig {
     pub elements: Vec<GroundPosition>,
 }
@@ -45,36 +118,20 @@ pub fn initialize_ground(world: &mut World, sprite_sheet: Handle<SpriteSheet>) {
         let mut transform = Transform::default();
         transform.set_translation_xyz(elem.pos[0], elem.pos[1], 0.0);
 
-        world
+        let mut builder = world
             .create_entity()
             .with(sprite_render)
-            .with(BoundingBox2D::new(elem.pos, 16.0, 24.0))
-            .with(transform)
-            .build();
-    }
-}
-
-fn compute_intersection_force(intersection: Vector2<f32>,
-                              transform: &mut Transform,
-                              player: &mut Player,
-                              physics: &mut PhysicsBox) {
-    // Hit the ground from the top
-    if intersection.y > 0.0 {
-        // Ground should always be at an integer position.
-        let y_pos = transform.translation().y;
-        player.on_ground = true;
-        transform.prepend_translation_y(intersection.y);
-        transform.prepend_translation_y(-(y_pos - y_pos.round()));
-        // Don't stop upwards motion.
-        physics.velocity.y = physics.velocity.y.max(0.0);
-    // Hit the ceiling from the bottom
-    } else if intersection.y < 0.0 {
-        transform.prepend_translation_y(intersection.y);
-        physics.velocity.y = -physics.velocity.y;
-    // Hit a wall.
-    } else if intersection.x != 0.0 {
-        transform.prepend_translation_x(intersection.x);
-        physics.velocity.x = -physics.velocity.x / 2.0;
+            .with(elem.layers)
+            .with(transform);
+        builder = match elem.shape.slope(elem.pos, elem.size) {
+            Some(slope) => builder.with(slope),
+            None if elem.shape == TileShape::Decoration => builder,
+            None => builder.with(BoundingBox2D::new(elem.pos, elem.size.x, elem.size.y)),
+        };
+        if elem.one_way {
+            builder = builder.with(OneWayPlatform);
+        }
+        builder.build();
     }
 }
 
@@ -86,16 +143,20 @@ pub struct ContactPassSystem;
 impl<'s> System<'s> for ContactPassSystem {
     type SystemData = (
         WriteStorage<'s, Player>,
-        ReadStorage<'s, BoundingBox2D>,
         WriteStorage<'s, PhysicsBox>,
-        WriteStorage<'s, Transform>,
+        ReadStorage<'s, Transform>,
+        Read<'s, SpatialHash>,
     );
 
-    fn run(&mut self, (mut players, grounds, mut physics_box, mut transforms): Self::SystemData) {
-        for (player, physics, transform) in (&mut players, &mut physics_box, &mut transforms).join() {
+    fn run(&mut self, (mut players, mut physics_box, transforms, hash): Self::SystemData) {
+        for (player, physics, transform) in (&mut players, &mut physics_box, &transforms).join() {
+            let was_on_ground = player.on_ground;
+            let previous_ground_velocity = player.ground_velocity;
+            player.ground_velocity = Vector2::new(0.0, 0.0);
             player.on_ground = false;
+            player.on_one_way = false;
+            player.ground_slope = 0.0;
             player.blocked = false;
-
             let player_position =
                 Vector2::new(transform.translation().x, transform.translation().y);
             let player_box = physics.bbox.translate(player_position);
@@ -107,49 +168,70 @@ impl<'s> System<'s> for ContactPassSystem {
                 player_box.corners.bottom() + 9.0
             );
             let box_below = player_box.translate(Vector2::new(0.0, -0.5));
-
-            let mut intersections = Vec::new();
+            // Probes for a wall either side, so a wall slide keeps hold of the wall without
+            // having to move into it.
+            let point_left = Vector2::new(player_box.corners.left() - 0.5,
+                                          player_box.corners.y_midpoint());
+            let point_right = Vector2::new(player_box.corners.right() + 0.5,
+                                           player_box.corners.y_midpoint());
+
+            let mut overlapping = false;
             let mut intersection_below = false;
+            let mut solid_below = false;
             let mut intersection_crouched = false;
             let mut intersection_above = false;
-
-            for ground in (&grounds).join() {
-
-                let intersection_check = ground
-                    .shortest_manhattan_move(&player_box, physics.velocity);
-
-                if let Some(intersection) = intersection_check {
-                    intersections.push(-intersection);
-                }
+            let mut wall_left = false;
+            let mut wall_right = false;
+
+            let nearby_space = box_below
+                .super_bounding_box(player_box.translate(Vector2::new(0.0, 1.0)))
+                .super_bounding_box(player_box.translate(Vector2::new(-1.0, 0.0)))
+                .super_bounding_box(player_box.translate(Vector2::new(1.0, 0.0)));
+            for solid in hash.query_statics(&nearby_space, &physics.layers) {
+                let ground = solid.bbox;
                 // Fall test. If the player moves down will they intersect the ground? If so they are
                 // on the ground. If not, they will fall.
-                intersection_below |= ground.intersects(&box_below);
+                if (solid.slope.is_some() || ground.intersects(&box_below))
+                    && solid.supports(&player_box, physics.drop_through)
+                {
+                    intersection_below = true;
+                    solid_below |= !solid.one_way;
+                    if solid.velocity.norm() > player.ground_velocity.norm() {
+                        player.ground_velocity = solid.velocity;
+                    }
+                    if let Some(slope) = solid.slope {
+                        player.ground_slope = slope.gradient();
+                    }
+                }
+                // One-way platforms and slopes never hem the player in.
+                if solid.one_way || solid.slope.is_some() {
+                    continue;
+                }
+                overlapping |= ground.intersects(&player_box);
                 intersection_crouched |= ground.contains(point_above_crouching);
                 intersection_above |= ground.contains(point_above);
+                wall_left |= ground.contains(point_left);
+                wall_right |= ground.contains(point_right);
+            }
+            player.wall_side = if physics.contacts.left || wall_left {
+                Some(-1.0)
+            } else if physics.contacts.right || wall_right {
+                Some(1.0)
+            } else {
+                None
+            };
+            player.on_ground = intersection_below || (physics.contacts.down && !physics.drop_through);
+            player.on_one_way = intersection_below && !solid_below;
+
+            // Leaving a moving platform keeps its momentum.
+            if was_on_ground && !player.on_ground {
+                physics.velocity.x += previous_ground_velocity.x;
+                physics.velocity.y += previous_ground_velocity.y.max(0.0);
             }
-            player.on_ground = intersection_below;
 
-            if intersection_below && intersection_above && (intersection_crouched || intersections.len() > 0) {
+            if player.on_ground && intersection_above && (intersection_crouched || overlapping) {
                 // The player is in a cramped space and colliding with an object. Force crouching.
                 player.blocked = true;
-                break;
-            }
-
-            let mut minimum_intersection: Option<Vector2<f32>> = None;
-            for intersection in intersections {
-                if let Some(min_intersection) = minimum_intersection {
-                    let int_sum = intersection.x.abs() + intersection.y.abs();
-                    let min_int_sum = min_intersection.x.abs() + min_intersection.y.abs();
-                    if int_sum < min_int_sum {
-                        minimum_intersection = Some(intersection);
-                    }
-                } else {
-                    minimum_intersection = Some(intersection);
-                }
-            }
-
-            if let Some(intersection) = minimum_intersection {
-                compute_intersection_force(intersection, transform, player, physics);
             }
         }
     }
@@ -0,0 +1,113 @@
+use amethyst::{
+    assets::Handle,
+    derive::SystemDesc,
+    ecs::prelude::{Join, Read, ReadStorage, System, SystemData, World, Write, WriteStorage},
+    prelude::*,
+    renderer::{SpriteRender, SpriteSheet},
+    ui::{UiFinder, UiImage, UiPrefab, UiText},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::player::{Player, PowerUp};
+use crate::game_data::FixedTimestep;
+use crate::level::LevelProgress;
+
+/// Ids of the widgets in `assets/ui/hud.ron` the `HudSystem` fills in.
+pub const SCORE_WIDGET: &str = "score";
+pub const MONEY_WIDGET: &str = "money";
+pub const LIVES_WIDGET: &str = "lives";
+pub const POWER_UP_WIDGET: &str = "power_up";
+pub const TIMER_WIDGET: &str = "timer";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HudConfig {
+    /// Sprite in the items sprite sheet shown for each power-up. Power-ups without one show
+    /// nothing.
+    #[serde(default)]
+    pub power_up_icons: HashMap<PowerUp, usize>,
+}
+
+/// Sprite sheet the power-up icon is taken from.
+pub struct HudSprites {
+    pub items: Handle<SpriteSheet>,
+}
+
+pub fn initialize_hud(world: &mut World, prefab: Handle<UiPrefab>, items: Handle<SpriteSheet>) {
+    world.insert(HudSprites { items });
+    world.create_entity().with(prefab).build();
+}
+
+/// "m:ss", for the level timer.
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.0) as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Keeps each HUD widget up to date with the player and level.
+#[derive(SystemDesc)]
+pub struct HudSystem;
+
+impl<'s> System<'s> for HudSystem {
+    type SystemData = (
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+        WriteStorage<'s, UiImage>,
+        ReadStorage<'s, Player>,
+        Read<'s, LevelProgress>,
+        Read<'s, HudConfig>,
+        Option<Read<'s, HudSprites>>,
+    );
+
+    fn run(
+        &mut self,
+        (finder, mut texts, mut images, players, progress, config, sprites): Self::SystemData,
+    ) {
+        let player = match (&players).join().next() {
+            Some(player) => player,
+            None => return,
+        };
+        let mut set_text = |id: &str, text: String| {
+            if let Some(widget) = finder.find(id).and_then(|entity| texts.get_mut(entity)) {
+                if widget.text != text {
+                    widget.text = text;
+                }
+            }
+        };
+        set_text(SCORE_WIDGET, player.score.to_string());
+        set_text(MONEY_WIDGET, format!("${}", player.money));
+        set_text(LIVES_WIDGET, format!("x{}", player.lives));
+        set_text(TIMER_WIDGET, format_time(progress.time));
+
+        let icon = finder.find(POWER_UP_WIDGET).and_then(|entity| images.get_mut(entity));
+        if let Some(icon) = icon {
+            let sprite = player
+                .power_up
+                .and_then(|power_up| config.power_up_icons.get(&power_up))
+                .and_then(|&sprite_number| {
+                    Some(SpriteRender {
+                        sprite_sheet: sprites.as_ref()?.items.clone(),
+                        sprite_number,
+                    })
+                });
+            *icon = match sprite {
+                Some(sprite) => UiImage::Sprite(sprite),
+                None => UiImage::SolidColor([0., 0., 0., 0.]),
+            };
+        }
+    }
+}
+
+/// Counts up how long the player has spent in the level, stopping once it's complete.
+#[derive(SystemDesc)]
+pub struct LevelTimerSystem;
+
+impl<'s> System<'s> for LevelTimerSystem {
+    type SystemData = (Write<'s, LevelProgress>, Read<'s, FixedTimestep>);
+
+    fn run(&mut self, (mut progress, timestep): Self::SystemData) {
+        if !progress.complete {
+            progress.time += timestep.step;
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use amethyst::{
+    core::{math::Vector3, Transform},
+    ecs::{Component, DenseVecStorage, Join, Read, ReadStorage, System, SystemData, WriteStorage},
+};
+
+use crate::game_data::FixedTimestep;
+
+/// The simulated translation of an entity at the last two fixed steps. The rendered Transform is
+/// interpolated between them so motion stays smooth when the frame rate and simulation rate
+/// differ.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SimulationPosition {
+    pub previous: Vector3<f32>,
+    pub current: Vector3<f32>,
+}
+
+impl SimulationPosition {
+    pub fn new(translation: Vector3<f32>) -> Self {
+        Self {
+            previous: translation,
+            current: translation,
+        }
+    }
+}
+
+impl Component for SimulationPosition {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Runs first in each fixed step. Puts the simulated translation back into the Transform so
+/// gameplay systems never see an interpolated position.
+#[derive(SystemDesc)]
+pub struct SimulationRestoreSystem;
+
+impl<'s> System<'s> for SimulationRestoreSystem {
+    type SystemData = (
+        WriteStorage<'s, SimulationPosition>,
+        WriteStorage<'s, Transform>,
+    );
+
+    fn run(&mut self, (mut positions, mut transforms): Self::SystemData) {
+        for (position, transform) in (&mut positions, &mut transforms).join() {
+            position.previous = position.current;
+            transform.set_translation(position.current);
+        }
+    }
+}
+
+/// Runs last in each fixed step and records the result of the simulation.
+#[derive(SystemDesc)]
+pub struct SimulationCaptureSystem;
+
+impl<'s> System<'s> for SimulationCaptureSystem {
+    type SystemData = (
+        WriteStorage<'s, SimulationPosition>,
+        ReadStorage<'s, Transform>,
+    );
+
+    fn run(&mut self, (mut positions, transforms): Self::SystemData) {
+        for (position, transform) in (&mut positions, &transforms).join() {
+            position.current = *transform.translation();
+        }
+    }
+}
+
+/// Runs every frame before the transform hierarchy is updated and blends the last two
+/// simulated positions for rendering.
+#[derive(SystemDesc)]
+pub struct TransformInterpolationSystem;
+
+impl<'s> System<'s> for TransformInterpolationSystem {
+    type SystemData = (
+        ReadStorage<'s, SimulationPosition>,
+        WriteStorage<'s, Transform>,
+        Read<'s, FixedTimestep>,
+    );
+
+    fn run(&mut self, (positions, mut transforms, timestep): Self::SystemData) {
+        for (position, transform) in (&positions, &mut transforms).join() {
+            transform.set_translation(position.previous.lerp(&position.current, timestep.alpha));
+        }
+    }
+}
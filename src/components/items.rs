@@ -14,16 +14,23 @@ use amethyst::{
 };
 use serde::{Deserialize, Serialize};
 
-use super::physics::{BoundingBox2D, PhysicsBox};
-use super::player::{PowerUp, Player, PlayerStance};
+use super::broadphase::SpatialHash;
+use super::physics::{BoundingBox2D, CollisionLayers, PhysicsBox};
+use super::player::{PowerUp, Player};
 use crate::geometry::Corners;
+use crate::level::LevelProgress;
 
 
 #[derive(Debug, Copy, Clone, Deserialize, Serialize)]
 pub enum ItemKind {
     Background,
-    Climbable,
-    Collectable(PowerUp)
+    Collectable(PowerUp),
+    /// Finishes the level when touched.
+    Goal,
+    /// The player restarts here after dying.
+    Checkpoint,
+    /// Hurts the player on contact.
+    Hazard,
 }
 
 #[derive(Debug)]
@@ -39,6 +46,18 @@ impl Item {
     }
 }
 
+impl ItemKind {
+    pub fn default_layers(&self) -> CollisionLayers {
+        match self {
+            ItemKind::Background => CollisionLayers::new(&[], &[]),
+            ItemKind::Collectable(_) | ItemKind::Goal | ItemKind::Checkpoint => {
+                CollisionLayers::item()
+            }
+            ItemKind::Hazard => CollisionLayers::hazard(),
+        }
+    }
+}
+
 
 impl Component for Item {
     type Storage = DenseVecStorage<Self>;
@@ -51,9 +70,12 @@ pub struct ItemPosition {
     pub kind: ItemKind,
     pub position: Vector2<f32>,
     pub corners: Corners,
+    /// Defaults to the layers for the item's kind.
+    #[serde(default)]
+    pub layers: Option<CollisionLayers>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ItemConfig {
     pub elements: Vec<ItemPosition>,
 }
@@ -85,7 +107,10 @@ pub fn initialize_items(world: &mut World, sprite_sheet: Handle<SpriteSheet>) {
         world.create_entity()
             .with(sprite_render)
             .with(Item::new(elem.kind))
-            .with(PhysicsBox::new(BoundingBox2D{corners: elem.corners}))
+            // Item corners are configured in world coordinates.
+            .with(PhysicsBox::new(
+                BoundingBox2D{corners: elem.corners}.translate(-elem.position))
+                .with_layers(elem.layers.unwrap_or_else(|| elem.kind.default_layers())))
             .with(transform)
             .build();
     }
@@ -98,45 +123,55 @@ pub struct InteractableItemSystem;
 impl<'s> System<'s> for InteractableItemSystem {
     type SystemData = (
         WriteStorage<'s, Player>,
-        WriteStorage<'s, PhysicsBox>,
+        ReadStorage<'s, PhysicsBox>,
         Entities<'s>,
         ReadStorage<'s, Item>,
         ReadStorage<'s, Transform>,
+        Read<'s, SpatialHash>,
+        Write<'s, LevelProgress>,
     );
 
-    fn run(&mut self, (mut players, mut physics_boxes, entities, items, transforms): Self::SystemData) {
-        let mut player_boxes: Vec<(&mut Player, BoundingBox2D)> = Vec::new();
+    fn run(
+        &mut self,
+        (mut players, physics_boxes, entities, items, transforms, hash, mut progress): Self::SystemData,
+    ) {
+        let mut collected = Vec::new();
         for (player, physics, transform) in (&mut players, &physics_boxes, &transforms).join() {
             let player_position =
                 Vector2::new(transform.translation().x, transform.translation().y);
             let player_box = physics.bbox.translate(player_position);
-            player_boxes.push((player, player_box));
-        }
-
-        for (player, player_box) in player_boxes {
-            let mut on_climbable = false;
-            for (entity, item_physics, item) in (&*entities, &physics_boxes, &items).join() {
-                if item_physics.bbox.intersects(&player_box) {
-                    match item.kind {
-                        ItemKind::Climbable => {
-                            on_climbable = true;
-                            if player.intent.y > 0.0 && player.stance != PlayerStance::Climbing {
-                                player.climb();
-                            }
-                        },
-                        ItemKind::Collectable(power_up) => {
-                            player.collect(power_up);
-                            player.score += item.value;
-                            entities.delete(entity).ok();
-                        },
-                        _ => {}
-                    }
 
+            for collider in hash.query_aabb(&player_box) {
+                if !physics.layers.interacts_with(&collider.layers) {
+                    continue;
+                }
+                let item = match items.get(collider.entity) {
+                    Some(item) => item,
+                    None => continue,
+                };
+                match item.kind {
+                    ItemKind::Collectable(power_up) => {
+                        // Already collected earlier this step.
+                        if collected.contains(&collider.entity) {
+                            continue;
+                        }
+                        player.collect(power_up);
+                        player.score += item.value;
+                        collected.push(collider.entity);
+                        entities.delete(collider.entity).ok();
+                    },
+                    ItemKind::Goal => {
+                        progress.complete = true;
+                    },
+                    ItemKind::Checkpoint => {
+                        if let Some(transform) = transforms.get(collider.entity) {
+                            let translation = transform.translation();
+                            progress.checkpoint = Some(Vector2::new(translation.x, translation.y));
+                        }
+                    },
+                    _ => {}
                 }
-            }
-            if !on_climbable && player.stance == PlayerStance::Climbing {
-                player.stance = PlayerStance::Standing;
             }
         }
-    } 
+    }
 }
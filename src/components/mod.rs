@@ -1,7 +1,17 @@
+pub mod abilities;
+pub mod animation;
 pub mod arena;
+pub mod broadphase;
 pub mod camera;
+pub mod climbable;
+pub mod combat;
+pub mod enemy;
 pub mod ground;
+pub mod hud;
+pub mod interpolation;
 pub mod items;
+pub mod parallax;
 pub mod physics;
+pub mod platform;
 pub mod player;
-pub mod score;
+pub mod player_state;
@@ -0,0 +1,206 @@
+use amethyst::{
+    assets::{AssetStorage, Handle},
+    core::{math::Vector2, Transform},
+    ecs::{Component, DenseVecStorage, Join, Read, ReadStorage, System, World, WriteStorage},
+    prelude::*,
+    renderer::{Camera, SpriteRender, SpriteSheet},
+};
+use serde::{Deserialize, Serialize};
+
+use super::camera::{CameraConfig, CameraEffects};
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum ParallaxRepeat {
+    /// Drawn once, at `position`.
+    None,
+    Horizontal,
+    Vertical,
+    Both,
+}
+
+impl Default for ParallaxRepeat {
+    fn default() -> Self {
+        ParallaxRepeat::Horizontal
+    }
+}
+
+impl ParallaxRepeat {
+    fn horizontal(self) -> bool {
+        self == ParallaxRepeat::Horizontal || self == ParallaxRepeat::Both
+    }
+
+    fn vertical(self) -> bool {
+        self == ParallaxRepeat::Vertical || self == ParallaxRepeat::Both
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ParallaxLayerConfig {
+    /// Texture and sprite sheet, relative to the assets directory. Loaded along with the level.
+    pub image: String,
+    pub sheet: String,
+    #[serde(default)]
+    pub sprite_num: usize,
+    /// How far the layer moves for each unit the camera moves. 0 stays fixed on screen, 1 moves
+    /// with the level.
+    pub scroll: Vector2<f32>,
+    #[serde(default)]
+    pub repeat: ParallaxRepeat,
+    /// Z of the layer. Further back is more negative, and must stay behind the level at 0.
+    pub depth: f32,
+    /// Bottom left corner of the layer when the camera is at the origin.
+    #[serde(default)]
+    pub position: Vector2<f32>,
+}
+
+/// Background layers, drawn back to front by `depth`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ParallaxConfig {
+    pub layers: Vec<ParallaxLayerConfig>,
+}
+
+/// One tile of a parallax layer. Repeating layers are made of enough tiles to cover the view,
+/// which the `ParallaxSystem` shuffles along as the camera moves.
+#[derive(Debug)]
+pub struct Parallax {
+    pub scroll: Vector2<f32>,
+    pub position: Vector2<f32>,
+    pub size: Vector2<f32>,
+    pub repeat: ParallaxRepeat,
+    pub column: usize,
+    pub row: usize,
+}
+
+impl Component for Parallax {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Where a tile goes along one axis. `index` picks the tile counting from the first one
+/// visible, if the layer repeats.
+fn place1d(
+    origin: f32,
+    camera: f32,
+    scroll: f32,
+    size: f32,
+    half_view: f32,
+    repeats: bool,
+    index: usize,
+) -> f32 {
+    let start = origin + camera * (1.0 - scroll);
+    let first = if repeats {
+        ((camera - half_view - start) / size).floor()
+    } else {
+        0.0
+    };
+    start + (first + index as f32) * size + size * 0.5
+}
+
+/// Tiles needed to cover `view` with tiles of `size`, with room to shuffle them along.
+fn tile_count(view: f32, size: f32, repeats: bool) -> usize {
+    if repeats {
+        (view / size).ceil() as usize + 1
+    } else {
+        1
+    }
+}
+
+/// Builds the level's parallax layers. `sprite_sheets` holds each layer's sprite sheet, in
+/// order.
+pub fn initialize_parallax(world: &mut World, sprite_sheets: &[Handle<SpriteSheet>]) {
+    let layers = world.read_resource::<ParallaxConfig>().layers.clone();
+    // Enough tiles for the view at its furthest zoom out.
+    let view = {
+        let config = world.read_resource::<CameraConfig>();
+        Vector2::new(config.width, config.height) / config.min_zoom
+    };
+
+    for (layer, sprite_sheet) in layers.iter().zip(sprite_sheets) {
+        let size = world
+            .read_resource::<AssetStorage<SpriteSheet>>()
+            .get(sprite_sheet)
+            .and_then(|sheet| sheet.sprites.get(layer.sprite_num))
+            .map(|sprite| Vector2::new(sprite.width, sprite.height));
+        let size = match size {
+            Some(size) => size,
+            None => {
+                warn!("Parallax layer {} has no sprite {}", layer.sheet, layer.sprite_num);
+                continue;
+            }
+        };
+
+        let columns = tile_count(view.x, size.x, layer.repeat.horizontal());
+        let rows = tile_count(view.y, size.y, layer.repeat.vertical());
+        for column in 0..columns {
+            for row in 0..rows {
+                let mut transform = Transform::default();
+                transform.set_translation_xyz(
+                    layer.position.x + (column as f32 + 0.5) * size.x,
+                    layer.position.y + (row as f32 + 0.5) * size.y,
+                    layer.depth,
+                );
+                world
+                    .create_entity()
+                    .with(SpriteRender {
+                        sprite_sheet: sprite_sheet.clone(),
+                        sprite_number: layer.sprite_num,
+                    })
+                    .with(Parallax {
+                        scroll: layer.scroll,
+                        position: layer.position,
+                        size,
+                        repeat: layer.repeat,
+                        column,
+                        row,
+                    })
+                    .with(transform)
+                    .build();
+            }
+        }
+    }
+}
+
+/// Moves the parallax layers relative to the camera.
+#[derive(SystemDesc)]
+pub struct ParallaxSystem;
+
+impl<'s> System<'s> for ParallaxSystem {
+    type SystemData = (
+        ReadStorage<'s, Parallax>,
+        ReadStorage<'s, Camera>,
+        WriteStorage<'s, Transform>,
+        Read<'s, CameraConfig>,
+        Read<'s, CameraEffects>,
+    );
+
+    fn run(&mut self, (parallaxes, cameras, mut transforms, config, effects): Self::SystemData) {
+        let camera = match (&cameras, &transforms).join().next() {
+            Some((_, transform)) => {
+                Vector2::new(transform.translation().x, transform.translation().y)
+            }
+            None => return,
+        };
+        let half_view = Vector2::new(config.width, config.height) * (0.5 / effects.zoom);
+
+        for (parallax, transform) in (&parallaxes, &mut transforms).join() {
+            let x = place1d(
+                parallax.position.x,
+                camera.x,
+                parallax.scroll.x,
+                parallax.size.x,
+                half_view.x,
+                parallax.repeat.horizontal(),
+                parallax.column,
+            );
+            let y = place1d(
+                parallax.position.y,
+                camera.y,
+                parallax.scroll.y,
+                parallax.size.y,
+                half_view.y,
+                parallax.repeat.vertical(),
+                parallax.row,
+            );
+            transform.set_translation_xyz(x, y, transform.translation().z);
+        }
+    }
+}
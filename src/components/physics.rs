@@ -1,10 +1,14 @@
 use amethyst::{
-    core::{math::Vector2, Time, Transform},
+    core::{math::Vector2, Transform},
     ecs::{
-        Component, DenseVecStorage, Join, Read, ReadStorage, System, SystemData, WriteStorage,
+        Component, DenseVecStorage, Entity, Join, NullStorage, Read, System, SystemData,
+        WriteStorage,
     },
 };
-use crate::geometry::{segment_intersection, Corners, IntersectionMode};
+use crate::game_data::FixedTimestep;
+use super::broadphase::SpatialHash;
+use crate::geometry::{slope_and_offset, Corners};
+use serde::{Deserialize, Serialize};
 
 /// Accelerate in a direction and return new velocity in that direction.
 pub fn accelerate1d(speed: f32, accel: f32, time_step: f32) -> f32 {
@@ -34,54 +38,6 @@ impl BoundingBox2D {
         }
     }
 
-    pub(self) fn manhattan_move(&self, bbox: &BoundingBox2D) -> Option<Vector2<f32>> {
-        if !self.intersects(&bbox) {
-            return None
-        }
-
-        let x_val = {
-            let right = bbox.corners.left() - self.corners.right();
-            let left = bbox.corners.right() - self.corners.left();
-            if right.abs() > left.abs() {
-                left
-            } else {
-                right
-            }
-        };
-
-        let y_val = {
-            let top = bbox.corners.bottom() - self.corners.top();
-            let bottom = bbox.corners.top() - self.corners.bottom();
-            if top.abs() > bottom.abs() {
-                bottom
-            } else {
-                top
-            }
-        };
-
-        Some(Vector2::new(x_val, y_val))
-    }
-
-    pub fn intersects_with_segment(&self, line_segment: [Vector2<f32>; 2]) -> Option<Vector2<f32>> {
-        let sides: [[Vector2<f32>; 2]; 4] = [
-            [Vector2::new(self.corners.left(), self.corners.bottom()),
-             Vector2::new(self.corners.right(), self.corners.bottom())],
-            [Vector2::new(self.corners.left(), self.corners.bottom()),
-             Vector2::new(self.corners.left(), self.corners.top())],
-            [Vector2::new(self.corners.right(), self.corners.bottom()),
-             Vector2::new(self.corners.right(), self.corners.top())],
-            [Vector2::new(self.corners.left(), self.corners.top()),
-             Vector2::new(self.corners.right(), self.corners.top())]
-        ];
-
-        for &side in sides.into_iter() {
-            if let Some(intersection) = segment_intersection(line_segment, side, IntersectionMode::ParallelDoesNotIntersect) {
-                return Some(intersection);
-            }
-        }
-        None
-    }
-
     pub fn super_bounding_box(&self, bbox: BoundingBox2D) -> BoundingBox2D {
         let left_min = self.corners.left().min(bbox.corners.left());
         let right_max = self.corners.right().max(bbox.corners.right());
@@ -102,45 +58,55 @@ impl BoundingBox2D {
         }
     }
 
-    /// Finds the move which minimizes distance and maximizes for the direction of the velocity.
-    pub fn shortest_manhattan_move(
-        &self,
-        bbox: &BoundingBox2D,
-        trajectory: Vector2<f32>,
-    ) -> Option<Vector2<f32>> {
-        // Check from this side
-        let intersection_vector = self.manhattan_move(bbox)?;
-
-        let hypotenuse = (trajectory.x.powf(2.) + trajectory.y.powf(2.)).sqrt();
-        let direction_of_impact = if trajectory.x.abs() > hypotenuse * (2./3.) {
-            Vector2::new(trajectory.x.signum(), 0.0)
-        } else if trajectory.y.abs() > hypotenuse * (2./3.) {
-            Vector2::new(0.0, trajectory.y.signum())
+    /// Sweeps this box along `displacement` against a static box. Returns the fraction of the
+    /// displacement at which the boxes first touch and the normal of the surface that was hit.
+    pub fn sweep(&self, displacement: Vector2<f32>, bbox: &BoundingBox2D) -> Option<SweepHit> {
+        let (x_entry, x_exit) = axis_sweep(
+            (self.corners.left(), self.corners.right()),
+            (bbox.corners.left(), bbox.corners.right()),
+            displacement.x,
+        );
+        let (y_entry, y_exit) = axis_sweep(
+            (self.corners.bottom(), self.corners.top()),
+            (bbox.corners.bottom(), bbox.corners.top()),
+            displacement.y,
+        );
+
+        let entry = x_entry.max(y_entry);
+        let exit = x_exit.min(y_exit);
+        // Boxes which already overlap have an entry time of negative infinity and are not swept
+        // contacts. sweep_move pushes boxes out of solids before sweeping.
+        if entry > exit || entry < 0.0 || entry > 1.0 {
+            return None;
+        }
+
+        let normal = if x_entry > y_entry {
+            Vector2::new(-displacement.x.signum(), 0.0)
         } else {
-            Vector2::new(0.0, 0.0)
+            Vector2::new(0.0, -displacement.y.signum())
         };
-        println!("vec: {:?}, direction: {:?}", intersection_vector, direction_of_impact);
-
-        // NOTE: this check is complicated because small impacts show up as small intersections,
-        // but the other direction might be large if it is near the middle, however large impacts
-        // show up as greater intersections in both directions and we must eject the collision in
-        // the opposite direction of the impact. We have to heuristically determine whether it was
-        // a small impact or a large impact.
-        if intersection_vector.x.abs() > intersection_vector.y.abs() {
-            // Horizontal intersection
-            if direction_of_impact.x != 0.0 {
-                Some(Vector2::new(intersection_vector.x, 0.0))
-            } else {
-                Some(Vector2::new(0.0, intersection_vector.y))
-            }
-        } else {
-            // Vertical intersection
-            if direction_of_impact.y != 0.0 {
-                Some(Vector2::new(0.0, intersection_vector.y))
-            } else {
-                Some(Vector2::new(intersection_vector.x, 0.0))
-            }
+        Some(SweepHit { time: entry, normal })
+    }
+
+    /// The shortest move along a single axis which takes this box out of `bbox`, and the normal
+    /// of the face it is pushed out of. None if they overlap by no more than MINIMUM_CLIP.
+    pub fn penetration(&self, bbox: &BoundingBox2D) -> Option<(Vector2<f32>, Vector2<f32>)> {
+        let exits = [
+            (bbox.corners.right() - self.corners.left(), Vector2::new(1.0, 0.0)),
+            (self.corners.right() - bbox.corners.left(), Vector2::new(-1.0, 0.0)),
+            (bbox.corners.top() - self.corners.bottom(), Vector2::new(0.0, 1.0)),
+            (self.corners.top() - bbox.corners.bottom(), Vector2::new(0.0, -1.0)),
+        ];
+        let (depth, normal) = exits
+            .iter()
+            .cloned()
+            .fold((std::f32::INFINITY, Vector2::new(0.0, 0.0)), |shortest, exit| {
+                if exit.0 < shortest.0 { exit } else { shortest }
+            });
+        if depth <= MINIMUM_CLIP {
+            return None;
         }
+        Some((normal * depth, normal))
     }
 
     pub fn intersects(&self, bbox: &BoundingBox2D) -> bool {
@@ -208,15 +174,228 @@ impl Component for BoundingBox2D {
     type Storage = DenseVecStorage<Self>;
 }
 
+/// The first contact found when sweeping a box along a displacement.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SweepHit {
+    /// Fraction of the displacement travelled before the contact, in [0, 1].
+    pub time: f32,
+    /// Normal of the surface which was hit, pointing back towards the moving box.
+    pub normal: Vector2<f32>,
+}
+
+/// Entry and exit times of a moving interval against a static one along a single axis.
+fn axis_sweep(moving: (f32, f32), fixed: (f32, f32), delta: f32) -> (f32, f32) {
+    // Gaps within MINIMUM_CLIP of touching are treated as touching so that floating point error
+    // can't push a resting box through the surface it rests on.
+    let clip = |gap: f32| if gap < 0.0 && gap > -MINIMUM_CLIP { 0.0 } else { gap };
+    if delta > 0.0 {
+        (clip(fixed.0 - moving.1) / delta, (fixed.1 - moving.0) / delta)
+    } else if delta < 0.0 {
+        (-clip(moving.0 - fixed.1) / delta, (fixed.0 - moving.1) / delta)
+    } else if moving.1 > fixed.0 && moving.0 < fixed.1 {
+        (std::f32::NEG_INFINITY, std::f32::INFINITY)
+    } else {
+        (std::f32::INFINITY, std::f32::NEG_INFINITY)
+    }
+}
+
+/// Which sides of a PhysicsBox touched a static collider during its last move.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Contacts {
+    pub down: bool,
+    pub up: bool,
+    pub left: bool,
+    pub right: bool,
+    /// Rise over run of the slope being stood on, zero on flat ground.
+    pub slope: f32,
+}
+
+impl Contacts {
+    pub fn add(&mut self, normal: Vector2<f32>) {
+        if normal.y > 0.0 {
+            self.down = true;
+        } else if normal.y < 0.0 {
+            self.up = true;
+        }
+        if normal.x > 0.0 {
+            self.left = true;
+        } else if normal.x < 0.0 {
+            self.right = true;
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.down || self.up || self.left || self.right
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Layer {
+    Ground,
+    Player,
+    Enemy,
+    Item,
+    Climbable,
+    Projectile,
+    /// Spikes and anything else which hurts the player on contact.
+    Hazard,
+}
+
+/// A set of collision layers packed into a bitset. Written in RON as a list of layers.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "Vec<Layer>", into = "Vec<Layer>")]
+pub struct LayerSet(pub u32);
+
+impl LayerSet {
+    pub const EMPTY: LayerSet = LayerSet(0);
+    pub const ALL: LayerSet = LayerSet(std::u32::MAX);
+
+    pub fn of(layers: &[Layer]) -> Self {
+        layers.iter().fold(LayerSet::EMPTY, |set, &layer| set.with(layer))
+    }
+
+    pub fn with(self, layer: Layer) -> Self {
+        LayerSet(self.0 | 1 << layer as u32)
+    }
+
+    pub fn contains(&self, layer: Layer) -> bool {
+        self.0 & 1 << layer as u32 != 0
+    }
+
+    pub fn intersects(&self, other: LayerSet) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl From<Vec<Layer>> for LayerSet {
+    fn from(layers: Vec<Layer>) -> Self {
+        LayerSet::of(&layers)
+    }
+}
+
+impl From<LayerSet> for Vec<Layer> {
+    fn from(set: LayerSet) -> Self {
+        [
+            Layer::Ground,
+            Layer::Player,
+            Layer::Enemy,
+            Layer::Item,
+            Layer::Climbable,
+            Layer::Projectile,
+            Layer::Hazard,
+        ]
+        .iter()
+        .cloned()
+        .filter(|&layer| set.contains(layer))
+        .collect()
+    }
+}
+
+/// The layers a collider belongs to and the layers it collides with. A collider only reacts to
+/// another when its mask contains one of the other's layers.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CollisionLayers {
+    pub membership: LayerSet,
+    pub mask: LayerSet,
+}
+
+impl Default for CollisionLayers {
+    fn default() -> Self {
+        Self {
+            membership: LayerSet::EMPTY,
+            mask: LayerSet::of(&[Layer::Ground]),
+        }
+    }
+}
+
+impl CollisionLayers {
+    pub fn new(membership: &[Layer], mask: &[Layer]) -> Self {
+        Self {
+            membership: LayerSet::of(membership),
+            mask: LayerSet::of(mask),
+        }
+    }
+
+    pub fn interacts_with(&self, other: &CollisionLayers) -> bool {
+        self.mask.intersects(other.membership)
+    }
+
+    pub fn ground() -> Self {
+        CollisionLayers::new(&[Layer::Ground], &[Layer::Player, Layer::Enemy, Layer::Projectile])
+    }
+
+    pub fn player() -> Self {
+        CollisionLayers::new(
+            &[Layer::Player],
+            &[
+                Layer::Ground,
+                Layer::Enemy,
+                Layer::Item,
+                Layer::Climbable,
+                Layer::Projectile,
+                Layer::Hazard,
+            ],
+        )
+    }
+
+    pub fn enemy() -> Self {
+        CollisionLayers::new(&[Layer::Enemy], &[Layer::Ground, Layer::Player, Layer::Projectile])
+    }
+
+    pub fn projectile() -> Self {
+        CollisionLayers::new(&[Layer::Projectile], &[Layer::Ground, Layer::Enemy])
+    }
+
+    pub fn enemy_projectile() -> Self {
+        CollisionLayers::new(&[Layer::Projectile], &[Layer::Ground, Layer::Player])
+    }
+
+    pub fn item() -> Self {
+        CollisionLayers::new(&[Layer::Item], &[Layer::Player])
+    }
+
+    pub fn hazard() -> Self {
+        CollisionLayers::new(&[Layer::Hazard], &[Layer::Player])
+    }
+
+    pub fn climbable() -> Self {
+        CollisionLayers::new(&[Layer::Climbable], &[Layer::Player])
+    }
+}
+
+/// Static colliders without this component are treated as ground.
+impl Component for CollisionLayers {
+    type Storage = DenseVecStorage<Self>;
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct PhysicsBox {
     pub bbox: BoundingBox2D,
     pub velocity: Vector2<f32>,
+    pub layers: CollisionLayers,
+    /// Fall through one-way platforms instead of landing on them.
+    pub drop_through: bool,
+    /// Set when a kinematic body pushed this box into a static collider.
+    pub crushed: bool,
+    /// Set by MoveExecutionSystem
+    pub contacts: Contacts,
 }
 
 impl PhysicsBox {
     pub fn new(bbox: BoundingBox2D) -> Self {
-        Self{bbox, velocity: Vector2::new(0.0, 0.0)}
+        Self{
+            bbox,
+            velocity: Vector2::new(0.0, 0.0),
+            layers: CollisionLayers::default(),
+            drop_through: false,
+            crushed: false,
+            contacts: Contacts::default(),
+        }
+    }
+
+    pub fn with_layers(mut self, layers: CollisionLayers) -> Self {
+        self.layers = layers;
+        self
     }
 }
 
@@ -229,85 +408,483 @@ pub fn euclidean_distance(line_segment: [Vector2<f32>; 2]) -> f32 {
      (line_segment[1].x - line_segment[0].x).powf(2.0)).sqrt()
 }
 
+/// A static collider which is moved along a path rather than by the movement solver.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct KinematicBody {
+    /// Velocity over the last step.
+    pub velocity: Vector2<f32>,
+}
+
+impl Component for KinematicBody {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Marks a static collider which is only solid when landed on from above.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct OneWayPlatform;
+
+impl Component for OneWayPlatform {
+    type Storage = NullStorage<Self>;
+}
+
+/// A sloped static collider. Boxes stand on the surface line at their horizontal midpoint. Its
+/// sides, up to where they meet the surface, and its flat `bottom` are solid.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Slope {
+    /// Left and right ends of the walkable surface, in world coordinates.
+    pub surface: [Vector2<f32>; 2],
+    pub bottom: f32,
+}
+
+impl Slope {
+    /// Rise over run of the surface.
+    pub fn gradient(&self) -> f32 {
+        slope_and_offset(self.surface).0
+    }
+
+    pub fn height_at(&self, x: f32) -> Option<f32> {
+        if x < self.surface[0].x || x > self.surface[1].x {
+            return None;
+        }
+        let (m, b) = slope_and_offset(self.surface);
+        Some(m * x + b)
+    }
+
+    pub fn bbox(&self) -> BoundingBox2D {
+        BoundingBox2D {
+            corners: Corners {
+                bottom_left: Vector2::new(self.surface[0].x, self.bottom),
+                top_right: Vector2::new(
+                    self.surface[1].x,
+                    self.surface[0].y.max(self.surface[1].y),
+                ),
+            },
+        }
+    }
+}
+
+impl Component for Slope {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Furthest a grounded box is pulled down onto a slope it is walking down.
+pub const SLOPE_SNAP_DISTANCE: f32 = 4.0;
+/// Furthest a box is pushed up onto a slope it has sunk into.
+pub const SLOPE_MAX_PENETRATION: f32 = 8.0;
+/// Ledge height a box walking on a slope can step onto, so the top of a slope can meet flat
+/// ground without snagging on its corner.
+pub const SLOPE_STEP_HEIGHT: f32 = 4.0;
+
+/// A static collider as seen by the movement solver.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Solid {
+    pub entity: Entity,
+    pub bbox: BoundingBox2D,
+    pub one_way: bool,
+    pub slope: Option<Slope>,
+    /// Velocity of a kinematic collider, zero for ones which never move.
+    pub velocity: Vector2<f32>,
+}
+
+impl Solid {
+    /// Whether a hit with this normal should stop the moving box, positioned where it touches.
+    /// The walkable surface of a slope is left to `resolve_slopes`; its sides below the surface
+    /// and its flat underside block like any other solid.
+    pub fn blocks(&self, normal: Vector2<f32>, drop_through: bool, moving: &BoundingBox2D) -> bool {
+        if self.slope.is_some() {
+            return normal.y < 0.0
+                || (normal.x != 0.0 && moving.corners.bottom() < self.top(normal) - MINIMUM_CLIP);
+        }
+        !self.one_way || (normal.y > 0.0 && !drop_through)
+    }
+
+    /// Top of the face hit with `normal`. For a slope's side this is where the surface meets
+    /// that side rather than the top of its bounding box.
+    pub fn top(&self, normal: Vector2<f32>) -> f32 {
+        match self.slope {
+            Some(slope) if normal.x < 0.0 => slope.surface[0].y,
+            Some(slope) if normal.x > 0.0 => slope.surface[1].y,
+            _ => self.bbox.corners.top(),
+        }
+    }
+
+    /// Whether a box is resting on or above the collider's top surface.
+    pub fn supports(&self, moving: &BoundingBox2D, drop_through: bool) -> bool {
+        let bottom = moving.corners.bottom();
+        if let Some(slope) = self.slope {
+            return match slope.height_at(moving.corners.x_midpoint()) {
+                Some(height) => bottom <= height + 0.5 && bottom >= height - SLOPE_MAX_PENETRATION,
+                None => false,
+            };
+        }
+        !self.one_way || (!drop_through && bottom >= self.bbox.corners.top() - MINIMUM_CLIP)
+    }
+}
+
+/// Options for a single sweep_move.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SweepOptions {
+    /// Fall through one-way platforms.
+    pub drop_through: bool,
+    /// The box was standing on something, so it should stay stuck to slopes it walks down.
+    pub grounded: bool,
+    /// Ledges up to this height are stepped over instead of blocking horizontal movement.
+    pub step_height: f32,
+}
+
+/// Lifts or lowers a box onto the highest slope under its midpoint.
+fn resolve_slopes(
+    moving: &BoundingBox2D,
+    velocity: Vector2<f32>,
+    statics: &[Solid],
+    grounded: bool,
+) -> Option<(f32, Slope)> {
+    let foot = Vector2::new(moving.corners.x_midpoint(), moving.corners.bottom());
+    let mut best: Option<(f32, Slope)> = None;
+    for slope in statics.iter().filter_map(|solid| solid.slope) {
+        let height = match slope.height_at(foot.x) {
+            Some(height) => height,
+            None => continue,
+        };
+        let depth = height - foot.y;
+        let sunk = depth > 0.0 && depth <= SLOPE_MAX_PENETRATION;
+        let hovering = grounded && velocity.y <= 0.0 && depth <= 0.0 && -depth <= SLOPE_SNAP_DISTANCE;
+        if !(sunk || hovering) {
+            continue;
+        }
+        match best {
+            Some((best_depth, _)) if best_depth >= depth => {}
+            _ => best = Some((depth, slope)),
+        }
+    }
+    best
+}
+
+/// Longest distance a box may travel in a single sub-step.
+pub const MAX_SUBSTEP_DISTANCE: f32 = 4.0;
+/// Upper bound on the number of sub-steps in a single frame.
+pub const MAX_SUBSTEPS: usize = 8;
+/// Number of contacts which may be resolved within a single sub-step.
+pub const MAX_CONTACT_ITERATIONS: usize = 4;
+
+/// The outcome of moving a box through a set of static colliders.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SweepResult {
+    pub translation: Vector2<f32>,
+    pub velocity: Vector2<f32>,
+    pub contacts: Contacts,
+}
+
+/// Moves `moving` by `displacement`, stopping at the first contact with any of `statics` and
+/// sliding along its surface for the remainder of the move. Velocity into a contact surface is
+/// removed so the box comes to rest against it instead of bouncing.
+pub fn sweep_move(
+    moving: BoundingBox2D,
+    displacement: Vector2<f32>,
+    velocity: Vector2<f32>,
+    statics: &[Solid],
+    options: SweepOptions,
+) -> SweepResult {
+    let mut result = SweepResult {
+        translation: Vector2::new(0.0, 0.0),
+        velocity,
+        contacts: Contacts::default(),
+    };
+
+    // A box which starts inside a solid, such as one spawned or pushed into it, is pushed out
+    // the shortest way first rather than moving through it.
+    for solid in statics.iter().filter(|solid| !solid.one_way && solid.slope.is_none()) {
+        let current = moving.translate(result.translation);
+        if let Some((push, normal)) = current.penetration(&solid.bbox) {
+            result.translation += push;
+            result.contacts.add(normal);
+            if result.velocity.x * normal.x < 0.0 {
+                result.velocity.x = 0.0;
+            }
+            if result.velocity.y * normal.y < 0.0 {
+                result.velocity.y = 0.0;
+            }
+        }
+    }
+
+    let distance = euclidean_distance([Vector2::new(0.0, 0.0), displacement]);
+    let substeps = ((distance / MAX_SUBSTEP_DISTANCE).ceil() as usize).max(1).min(MAX_SUBSTEPS);
+    let mut step = displacement / substeps as f32;
+
+    for _ in 0..substeps {
+        let mut remaining = step;
+        for _ in 0..MAX_CONTACT_ITERATIONS {
+            if remaining.x == 0.0 && remaining.y == 0.0 {
+                break;
+            }
+            let current = moving.translate(result.translation);
+
+            let mut first_hit: Option<(SweepHit, &Solid)> = None;
+            for solid in statics {
+                // One-way platforms are only hit from above. A box already partway through one,
+                // or standing on a slope, has a negative entry time and passes through.
+                let hit = match current.sweep(remaining, &solid.bbox) {
+                    Some(hit) => hit,
+                    None => continue,
+                };
+                let touching = current.translate(remaining * hit.time);
+                if !solid.blocks(hit.normal, options.drop_through, &touching) {
+                    continue;
+                }
+                match first_hit {
+                    Some((first, _)) if first.time <= hit.time => {}
+                    _ => first_hit = Some((hit, solid)),
+                }
+            }
+
+            let (hit, solid) = match first_hit {
+                Some(first_hit) => first_hit,
+                None => {
+                    result.translation += remaining;
+                    break;
+                }
+            };
+
+            result.translation += remaining * hit.time;
+            let current = moving.translate(result.translation);
+
+            let bbox = &solid.bbox;
+            if hit.normal.x != 0.0 {
+                let ledge = solid.top(hit.normal) - current.corners.bottom();
+                if ledge > 0.0 && ledge <= options.step_height {
+                    result.translation.y += ledge;
+                    remaining *= 1.0 - hit.time;
+                    continue;
+                }
+            }
+
+            // Rest exactly against the face that was hit.
+            if hit.normal.y > 0.0 {
+                result.translation.y += bbox.corners.top() - current.corners.bottom();
+            } else if hit.normal.y < 0.0 {
+                result.translation.y += bbox.corners.bottom() - current.corners.top();
+            } else if hit.normal.x > 0.0 {
+                result.translation.x += bbox.corners.right() - current.corners.left();
+            } else if hit.normal.x < 0.0 {
+                result.translation.x += bbox.corners.left() - current.corners.right();
+            }
+            result.contacts.add(hit.normal);
+
+            // Slide: drop the part of the motion which points into the surface.
+            remaining *= 1.0 - hit.time;
+            if hit.normal.x != 0.0 {
+                remaining.x = 0.0;
+                step.x = 0.0;
+                if result.velocity.x * hit.normal.x < 0.0 {
+                    result.velocity.x = 0.0;
+                }
+            } else {
+                remaining.y = 0.0;
+                step.y = 0.0;
+                if result.velocity.y * hit.normal.y < 0.0 {
+                    result.velocity.y = 0.0;
+                }
+            }
+        }
+
+        let current = moving.translate(result.translation);
+        let grounded = options.grounded || result.contacts.down;
+        if let Some((depth, slope)) = resolve_slopes(&current, result.velocity, statics, grounded) {
+            result.translation.y += depth;
+            result.contacts.down = true;
+            result.contacts.slope = slope.gradient();
+            result.velocity.y = result.velocity.y.max(0.0);
+        }
+    }
+    result
+}
+
 #[derive(SystemDesc)]
 pub struct MoveExecutionSystem;
 
 impl<'s> System<'s> for MoveExecutionSystem {
     type SystemData = (
         WriteStorage<'s, Transform>,
-        ReadStorage<'s, BoundingBox2D>,
         WriteStorage<'s, PhysicsBox>,
-        Read<'s, Time>
+        Read<'s, SpatialHash>,
+        Read<'s, FixedTimestep>
     );
 
-    fn run(&mut self, (mut transforms, bounding_boxes, mut physics_boxes, time): Self::SystemData) {
-        let time_step = time.delta_seconds();
-        let mut physix = Vec::new();
-
-        for (phys_transform, physics) in (&transforms, &physics_boxes).join() {
-            let phys_pos = {let vec = phys_transform.translation(); Vector2::new(vec.x, vec.y)};
-            let phys_box = physics.bbox.translate(phys_pos);
-            let direction = physics.velocity * time_step;
-            debug!("Phys: {:?} {:?}", phys_box, direction);
-            physix.push((phys_box, direction));
-        }
-
-        let mut moves: Vec<Option<(Vector2<f32>, Vector2<f32>)>> = Vec::new();
+    fn run(&mut self, (mut transforms, mut physics_boxes, hash, timestep): Self::SystemData) {
+        let time_step = timestep.step;
 
-        for (phys_box, direction) in physix {
-            let expected_position = BoundingBox2D{corners:
-                Corners{bottom_left: phys_box.corners.bottom_left() + direction,
-                        top_right: phys_box.corners.top_right() + direction}
-            };
-            let covered_space = phys_box.super_bounding_box(expected_position);
-
-            let ray_casts: Vec<[Vector2<f32>; 2]> = vec![
-                [phys_box.corners.bottom_left(), phys_box.corners.bottom_left() + direction],
-                [phys_box.corners.top_right(), phys_box.corners.top_right() + direction],
-                [phys_box.corners.bottom_right(), phys_box.corners.bottom_right() + direction],
-                [phys_box.corners.top_left(), phys_box.corners.top_left() + direction],
-            ];
-
-            'statics: for (static_transform, bbox) in (&transforms, &bounding_boxes).join() {
-                let static_box = bbox.translate(
-                    {let vec = static_transform.translation(); Vector2::new(vec.x, vec.y)});
-                if static_box.intersects(&covered_space) {
-                    for segment in &ray_casts {
-                        if let Some(intersection_point) = static_box.intersects_with_segment(*segment) {
-                            // 1. Find how far along the segment the intersection point is.
-                            let total_distance = euclidean_distance(*segment);
-                            let travelled_segment = [segment[0], segment[0] + intersection_point];
-                            let travelled_distance = euclidean_distance(travelled_segment);
-                            let remaining_distance_fraction = (total_distance - travelled_distance) / total_distance;
-                            let remaining_time = remaining_distance_fraction * time_step;
-                            // 2. Set a new velocity which is reversed and halved from the old.
-                            // NOTE: This makes assumptions about the static box's orientation.
-                            let new_velocity = -direction / 2.0;
-
-                            // 3. Move the remaining distance at the new velocity.
-                            let new_position = intersection_point + remaining_time * new_velocity;
-                            moves.push(Some((new_velocity, new_position))); 
-                            break 'statics;
-                        }
-                    }
-                    // If we didn't break, add a nothing move.
-                    moves.push(None);
-                }
+        for (transform, physics) in (&mut transforms, &mut physics_boxes).join() {
+            let displacement = physics.velocity * time_step;
+            // A box which isn't moving keeps touching whatever it touched last step.
+            if displacement.x == 0.0 && displacement.y == 0.0 {
+                continue;
             }
+            let position = {let vec = transform.translation(); Vector2::new(vec.x, vec.y)};
+            let moving = physics.bbox.translate(position);
+            let covered_space = moving.super_bounding_box(moving.translate(displacement))
+                .super_bounding_box(moving.translate(Vector2::new(0.0, -SLOPE_SNAP_DISTANCE)));
+            let statics = hash.query_statics(&covered_space, &physics.layers);
+            let box_below = moving.translate(Vector2::new(0.0, -0.5));
+            let standing = statics.iter().any(|solid| {
+                solid.bbox.intersects(&box_below) && solid.supports(&moving, physics.drop_through)
+            });
+            let options = SweepOptions {
+                drop_through: physics.drop_through,
+                grounded: physics.contacts.down || standing,
+                step_height: if physics.contacts.slope != 0.0 { SLOPE_STEP_HEIGHT } else { 0.0 },
+            };
+            let result = sweep_move(moving, displacement, physics.velocity, &statics, options);
+            debug!("Move: {:?} {:?} -> {:?}", moving, displacement, result);
+
+            transform.prepend_translation_x(result.translation.x);
+            transform.prepend_translation_y(result.translation.y);
+            physics.velocity = result.velocity;
+            physics.contacts = result.contacts;
         }
+    }
+}
 
-        for ((phys_transform, physics), phys_move) in (&mut transforms, &mut physics_boxes).join().zip(moves) {
-            if let Some((new_velocity, new_position)) = phys_move {
-                let phys_pos = {let vec = phys_transform.translation(); Vector2::new(vec.x, vec.y)};
-                let difference = new_position - phys_pos;
-                phys_transform.prepend_translation_x(difference.x);
-                phys_transform.prepend_translation_y(difference.y);
-                physics.velocity = new_velocity;
-            } else {
-                // Now move by the current velocity.
-                phys_transform.prepend_translation_x(physics.velocity.x * time_step);
-                phys_transform.prepend_translation_y(physics.velocity.y * time_step);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::ecs::{Builder, World, WorldExt};
+
+    fn solid(corner: Vector2<f32>, width: f32, height: f32) -> Solid {
+        Solid {
+            entity: World::new().create_entity().build(),
+            bbox: BoundingBox2D::new(corner, width, height),
+            one_way: false,
+            slope: None,
+            velocity: Vector2::new(0.0, 0.0),
         }
     }
+
+    /// A wide floor whose top is at `top`.
+    fn floor(top: f32, thickness: f32) -> Solid {
+        solid(Vector2::new(-100.0, top - thickness), 200.0, thickness)
+    }
+
+    fn moving(x: f32, y: f32) -> BoundingBox2D {
+        BoundingBox2D::new(Vector2::new(x, y), 8.0, 8.0)
+    }
+
+    fn assert_close(actual: Vector2<f32>, expected: Vector2<f32>) {
+        assert!(
+            (actual - expected).norm() < 1e-3,
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn sweep_reports_time_of_impact_and_normal() {
+        let wall = BoundingBox2D::new(Vector2::new(20.0, 0.0), 16.0, 16.0);
+        let hit = moving(0.0, 0.0).sweep(Vector2::new(24.0, 0.0), &wall).unwrap();
+        assert!((hit.time - 0.5).abs() < 1e-6);
+        assert_eq!(hit.normal, Vector2::new(-1.0, 0.0));
+
+        assert_eq!(moving(0.0, 0.0).sweep(Vector2::new(8.0, 0.0), &wall), None);
+    }
+
+    #[test]
+    fn stops_against_a_wall() {
+        let statics = [solid(Vector2::new(20.0, 0.0), 16.0, 16.0)];
+        let result = sweep_move(
+            moving(0.0, 0.0),
+            Vector2::new(20.0, 0.0),
+            Vector2::new(120.0, 0.0),
+            &statics,
+            SweepOptions::default(),
+        );
+        assert_close(result.translation, Vector2::new(12.0, 0.0));
+        assert_eq!(result.velocity, Vector2::new(0.0, 0.0));
+        assert!(result.contacts.right);
+    }
+
+    #[test]
+    fn slides_along_the_floor_after_landing() {
+        let statics = [floor(0.0, 16.0)];
+        let result = sweep_move(
+            moving(0.0, 10.0),
+            Vector2::new(10.0, -20.0),
+            Vector2::new(60.0, -120.0),
+            &statics,
+            SweepOptions::default(),
+        );
+        assert_close(result.translation, Vector2::new(10.0, -10.0));
+        assert_eq!(result.velocity, Vector2::new(60.0, 0.0));
+        assert!(result.contacts.down);
+        assert!(!result.contacts.left && !result.contacts.right);
+    }
+
+    #[test]
+    fn fast_boxes_do_not_tunnel_through_thin_walls() {
+        let statics = [solid(Vector2::new(50.0, -100.0), 1.0, 200.0)];
+        let result = sweep_move(
+            moving(0.0, 0.0),
+            Vector2::new(100.0, 0.0),
+            Vector2::new(6000.0, 0.0),
+            &statics,
+            SweepOptions::default(),
+        );
+        assert_close(result.translation, Vector2::new(42.0, 0.0));
+        assert!(result.contacts.right);
+    }
+
+    #[test]
+    fn fast_boxes_do_not_tunnel_through_thin_floors() {
+        let statics = [floor(0.0, 1.0)];
+        let result = sweep_move(
+            moving(0.0, 50.0),
+            Vector2::new(0.0, -200.0),
+            Vector2::new(0.0, -12000.0),
+            &statics,
+            SweepOptions::default(),
+        );
+        assert_close(result.translation, Vector2::new(0.0, -50.0));
+        assert!(result.contacts.down);
+    }
+
+    #[test]
+    fn boxes_inside_a_solid_are_pushed_out() {
+        let statics = [floor(0.0, 16.0)];
+        let result = sweep_move(
+            moving(0.0, -2.0),
+            Vector2::new(0.0, 0.0),
+            Vector2::new(0.0, -10.0),
+            &statics,
+            SweepOptions::default(),
+        );
+        assert_close(result.translation, Vector2::new(0.0, 2.0));
+        assert_eq!(result.velocity, Vector2::new(0.0, 0.0));
+        assert!(result.contacts.down);
+    }
+
+    #[test]
+    fn one_way_platforms_only_block_from_above() {
+        let mut platform = floor(21.0, 1.0);
+        platform.one_way = true;
+        let statics = [platform];
+
+        let rising = sweep_move(
+            moving(0.0, 0.0),
+            Vector2::new(0.0, 40.0),
+            Vector2::new(0.0, 100.0),
+            &statics,
+            SweepOptions::default(),
+        );
+        assert_close(rising.translation, Vector2::new(0.0, 40.0));
+        assert!(!rising.contacts.any());
+
+        let falling = sweep_move(
+            moving(0.0, 40.0),
+            Vector2::new(0.0, -40.0),
+            Vector2::new(0.0, -100.0),
+            &statics,
+            SweepOptions::default(),
+        );
+        assert_close(falling.translation, Vector2::new(0.0, -19.0));
+        assert!(falling.contacts.down);
+    }
 }
@@ -0,0 +1,332 @@
+use amethyst::{
+    assets::Handle,
+    core::{math::Vector2, Transform},
+    ecs::{
+        Component, DenseVecStorage, Entities, Join, Read, ReadStorage, System, SystemData, World,
+        WriteStorage,
+    },
+    prelude::*,
+    renderer::{SpriteRender, SpriteSheet},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::game_data::FixedTimestep;
+use super::broadphase::SpatialHash;
+use super::ground::default_tile_size;
+use super::interpolation::SimulationPosition;
+use super::physics::{
+    sweep_move, BoundingBox2D, CollisionLayers, KinematicBody, OneWayPlatform, PhysicsBox,
+    SweepOptions, MINIMUM_CLIP,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum PathMode {
+    /// Travel to the last waypoint and stop.
+    Linear,
+    /// Travel to the last waypoint, then back to the first, forever.
+    PingPong,
+    /// Travel to the last waypoint, then on to the first, forever.
+    Loop,
+}
+
+impl Default for PathMode {
+    fn default() -> Self {
+        PathMode::PingPong
+    }
+}
+
+/// How progress along each leg of a path is mapped to distance.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::Linear
+    }
+}
+
+impl Easing {
+    pub fn apply(&self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PlatformPosition {
+    pub sprite_num: usize,
+    /// A tile wide and high unless given.
+    #[serde(default)]
+    pub width: Option<f32>,
+    #[serde(default)]
+    pub height: Option<f32>,
+    /// Bottom left corner of the platform at each stop along its path.
+    pub waypoints: Vec<Vector2<f32>>,
+    /// Pixels per second.
+    pub speed: f32,
+    #[serde(default)]
+    pub mode: PathMode,
+    #[serde(default)]
+    pub easing: Easing,
+    #[serde(default)]
+    pub one_way: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PlatformConfig {
+    pub elements: Vec<PlatformPosition>,
+    /// Size of the level's tiles, taken from its tilemap if it has one.
+    #[serde(default = "default_tile_size")]
+    pub tile_size: Vector2<f32>,
+}
+
+impl Default for PlatformConfig {
+    fn default() -> Self {
+        Self {
+            elements: Vec::new(),
+            tile_size: default_tile_size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MovingPlatform {
+    pub waypoints: Vec<Vector2<f32>>,
+    pub speed: f32,
+    pub mode: PathMode,
+    pub easing: Easing,
+    from: usize,
+    to: usize,
+    /// Fraction of the current leg travelled.
+    progress: f32,
+    finished: bool,
+}
+
+impl MovingPlatform {
+    pub fn new(position: &PlatformPosition) -> Self {
+        Self {
+            waypoints: position.waypoints.clone(),
+            speed: position.speed,
+            mode: position.mode,
+            easing: position.easing,
+            from: 0,
+            to: 1,
+            progress: 0.0,
+            finished: position.waypoints.len() < 2,
+        }
+    }
+
+    pub fn position(&self) -> Vector2<f32> {
+        if self.waypoints.len() < 2 {
+            return self.waypoints.first().cloned().unwrap_or_else(|| Vector2::new(0.0, 0.0));
+        }
+        let from = self.waypoints[self.from];
+        let to = self.waypoints[self.to];
+        from + (to - from) * self.easing.apply(self.progress)
+    }
+
+    /// Moves along the path and returns the new position.
+    pub fn advance(&mut self, time_step: f32) -> Vector2<f32> {
+        if self.finished {
+            return self.position();
+        }
+        let length = (self.waypoints[self.to] - self.waypoints[self.from]).norm().max(MINIMUM_CLIP);
+        self.progress = (self.progress + self.speed * time_step / length).min(1.0);
+        if self.progress >= 1.0 {
+            self.next_leg();
+        }
+        self.position()
+    }
+
+    fn next_leg(&mut self) {
+        let last = self.waypoints.len() - 1;
+        let forward = self.to > self.from || (self.mode == PathMode::Loop && self.to == 0);
+        let next = match self.mode {
+            PathMode::Linear if self.to == last => None,
+            PathMode::Linear => Some(self.to + 1),
+            PathMode::Loop => Some((self.to + 1) % self.waypoints.len()),
+            PathMode::PingPong => {
+                if (forward && self.to == last) || (!forward && self.to == 0) {
+                    Some(self.from)
+                } else if forward {
+                    Some(self.to + 1)
+                } else {
+                    Some(self.to - 1)
+                }
+            }
+        };
+        match next {
+            Some(next) => {
+                self.from = self.to;
+                self.to = next;
+                self.progress = 0.0;
+            }
+            None => self.finished = true,
+        }
+    }
+}
+
+impl Component for MovingPlatform {
+    type Storage = DenseVecStorage<Self>;
+}
+
+const PLATFORM_PLANE: f32 = 0.0;
+
+pub fn initialize_platforms(world: &mut World, sprite_sheet: Handle<SpriteSheet>) {
+    let (elements, tile_size) = {
+        let config = world.read_resource::<PlatformConfig>();
+        (config.elements.clone(), config.tile_size)
+    };
+
+    for elem in elements {
+        let platform = MovingPlatform::new(&elem);
+        let start = platform.position();
+        let sprite_render = SpriteRender {
+            sprite_sheet: sprite_sheet.clone(),
+            sprite_number: elem.sprite_num,
+        };
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(start.x, start.y, PLATFORM_PLANE);
+
+        let mut builder = world
+            .create_entity()
+            .with(sprite_render)
+            .with(BoundingBox2D::new(
+                start,
+                elem.width.unwrap_or(tile_size.x),
+                elem.height.unwrap_or(tile_size.y),
+            ))
+            .with(CollisionLayers::ground())
+            .with(KinematicBody::default())
+            .with(platform)
+            .with(SimulationPosition::new(*transform.translation()))
+            .with(transform);
+        if elem.one_way {
+            builder = builder.with(OneWayPlatform);
+        }
+        builder.build();
+    }
+}
+
+/// Moves platforms along their paths. Boxes standing on a platform are carried with it, and
+/// boxes in its way are pushed. A box which can't be pushed out of the way is crushed.
+#[derive(SystemDesc)]
+pub struct PlatformMovementSystem;
+
+impl<'s> System<'s> for PlatformMovementSystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, MovingPlatform>,
+        WriteStorage<'s, KinematicBody>,
+        WriteStorage<'s, BoundingBox2D>,
+        ReadStorage<'s, OneWayPlatform>,
+        WriteStorage<'s, PhysicsBox>,
+        WriteStorage<'s, Transform>,
+        Read<'s, SpatialHash>,
+        Read<'s, FixedTimestep>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut platforms,
+            mut kinematic_bodies,
+            mut bounding_boxes,
+            one_way_platforms,
+            mut physics_boxes,
+            mut transforms,
+            hash,
+            timestep,
+        ): Self::SystemData,
+    ) {
+        let time_step = timestep.step;
+
+        let mut moves = Vec::new();
+        for (entity, platform, kinematic, bbox, transform) in (
+            &entities,
+            &mut platforms,
+            &mut kinematic_bodies,
+            &mut bounding_boxes,
+            &mut transforms,
+        )
+            .join()
+        {
+            let old_box = *bbox;
+            let delta = platform.advance(time_step) - old_box.corners.bottom_left();
+            kinematic.velocity = delta / time_step;
+            if delta.x == 0.0 && delta.y == 0.0 {
+                continue;
+            }
+            *bbox = old_box.translate(delta);
+            transform.prepend_translation_x(delta.x);
+            transform.prepend_translation_y(delta.y);
+            moves.push((entity, old_box, *bbox, delta, one_way_platforms.contains(entity)));
+        }
+
+        for (platform, old_box, new_box, delta, one_way) in moves {
+            for (physics, transform) in (&mut physics_boxes, &mut transforms).join() {
+                if !physics.layers.interacts_with(&CollisionLayers::ground()) {
+                    continue;
+                }
+                let position = Vector2::new(transform.translation().x, transform.translation().y);
+                let body = physics.bbox.translate(position);
+
+                let overlaps_x = body.corners.right() > old_box.corners.left()
+                    && body.corners.left() < old_box.corners.right();
+                let riding = overlaps_x
+                    && !physics.drop_through
+                    && (body.corners.bottom() - old_box.corners.top()).abs() <= MINIMUM_CLIP;
+
+                let push = if riding {
+                    delta
+                } else if !one_way && new_box.intersects(&body) {
+                    // Push out along whichever axis the platform moved into the body on.
+                    if overlaps_x {
+                        if delta.y > 0.0 {
+                            Vector2::new(0.0, new_box.corners.top() - body.corners.bottom())
+                        } else {
+                            Vector2::new(0.0, new_box.corners.bottom() - body.corners.top())
+                        }
+                    } else if delta.x > 0.0 {
+                        Vector2::new(new_box.corners.right() - body.corners.left(), 0.0)
+                    } else {
+                        Vector2::new(new_box.corners.left() - body.corners.right(), 0.0)
+                    }
+                } else {
+                    continue;
+                };
+
+                let covered_space = body.super_bounding_box(body.translate(push));
+                let statics: Vec<_> = hash
+                    .query_statics(&covered_space, &physics.layers)
+                    .into_iter()
+                    .filter(|solid| solid.entity != platform)
+                    .collect();
+                let options = SweepOptions {
+                    drop_through: physics.drop_through,
+                    grounded: riding,
+                    step_height: 0.0,
+                };
+                let result = sweep_move(body, push, Vector2::new(0.0, 0.0), &statics, options);
+                transform.prepend_translation_x(result.translation.x);
+                transform.prepend_translation_y(result.translation.y);
+
+                let moved = body.translate(result.translation);
+                if !one_way && new_box.intersects(&moved) {
+                    debug!("Crushed by platform: {:?}", moved);
+                    physics.crushed = true;
+                }
+            }
+        }
+    }
+}
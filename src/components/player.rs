@@ -1,19 +1,32 @@
 use amethyst::{
-    assets::Handle,
-    core::{math::Vector2, Time, Transform},
-    ecs::{Component, DenseVecStorage, Join, Read, ReadStorage, System, World, Write, WriteStorage},
+    assets::{Handle, Prefab},
+    core::{math::Vector2, Hidden, Transform},
+    ecs::{
+        Component, DenseVecStorage, Entities, Join, Read, ReadStorage, System, SystemData, World,
+        Write, WriteStorage,
+    },
     input::{InputHandler, StringBindings},
     prelude::*,
     renderer::{SpriteRender, SpriteSheet},
+    shrev::{EventChannel, ReaderId},
 };
 use serde::{Deserialize, Serialize};
 
+use crate::game_data::FixedTimestep;
 use crate::geometry::Corners;
+use super::abilities::Abilities;
+use super::animation::{AnimationPlayback, SpritePrefab};
+use super::broadphase::SpatialHash;
+use super::camera::{CameraConfig, CameraEffects};
+use super::climbable::{ClimbContact, ClimbableKind};
+use super::combat::{Attacker, HitEvent, Hurtbox};
+use super::interpolation::SimulationPosition;
 use super::physics::{
-    accelerate1d, decelerate1d, BoundingBox2D, PhysicsBox, MINIMUM_CLIP,
+    accelerate1d, decelerate1d, BoundingBox2D, CollisionLayers, Layer, PhysicsBox,
 };
+use super::player_state::{next_state, PlayerState};
 
-#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum PowerUp {
     KiArmor,
     KiStar,
@@ -38,6 +51,119 @@ pub struct PlayerConfig {
     pub jump_speed_running: f32,
     pub jump_speed_climbing: f32,
     pub fall_accel: f32,
+    /// How long after walking off a ledge the player can still jump.
+    #[serde(default = "default_coyote_time")]
+    pub coyote_time: f32,
+    /// How long a jump press is remembered, so pressing just before landing still jumps.
+    #[serde(default = "default_jump_buffer_time")]
+    pub jump_buffer_time: f32,
+    /// Upward speed is multiplied by this when jump is released early, for shorter hops.
+    #[serde(default = "default_jump_cut_multiplier")]
+    pub jump_cut_multiplier: f32,
+    /// Fastest fall while sliding down a wall.
+    #[serde(default = "default_wall_slide_speed")]
+    pub wall_slide_speed: f32,
+    /// Velocity of a wall jump, as if kicking off a wall on the left.
+    #[serde(default = "default_wall_jump_speed")]
+    pub wall_jump_speed: Vector2<f32>,
+    /// How long after a wall jump the player can't steer, so they can't cling straight back on.
+    #[serde(default = "default_wall_jump_lockout")]
+    pub wall_jump_lockout: f32,
+    /// Sideways speed when jumping off a climbable while steering.
+    #[serde(default = "default_climb_jump_sideways")]
+    pub climb_jump_sideways: f32,
+    /// Angular acceleration from steering while swinging on a rope.
+    #[serde(default = "default_rope_swing_accel")]
+    pub rope_swing_accel: f32,
+    /// Fraction of the swing lost each second.
+    #[serde(default = "default_rope_swing_damping")]
+    pub rope_swing_damping: f32,
+    /// Furthest a rope swings from hanging straight down, in radians.
+    #[serde(default = "default_rope_max_swing")]
+    pub rope_max_swing: f32,
+    /// How long one-way platforms are ignored after dropping through one.
+    #[serde(default = "default_drop_through_time")]
+    pub drop_through_time: f32,
+    #[serde(default = "CollisionLayers::player")]
+    pub layers: CollisionLayers,
+    /// How long the player can't be hurt again after taking damage.
+    #[serde(default = "default_invulnerable_time")]
+    pub invulnerable_time: f32,
+    /// Velocity away from whatever hurt the player.
+    #[serde(default = "default_knockback_speed")]
+    pub knockback_speed: Vector2<f32>,
+    /// How long the player has no control after being knocked back.
+    #[serde(default = "default_knockback_time")]
+    pub knockback_time: f32,
+    #[serde(default = "default_lives")]
+    pub lives: u32,
+    /// How long the death animation plays before respawning.
+    #[serde(default = "default_death_time")]
+    pub death_time: f32,
+}
+
+fn default_coyote_time() -> f32 {
+    0.1
+}
+
+fn default_jump_buffer_time() -> f32 {
+    0.12
+}
+
+fn default_jump_cut_multiplier() -> f32 {
+    0.5
+}
+
+fn default_wall_slide_speed() -> f32 {
+    60.0
+}
+
+fn default_wall_jump_speed() -> Vector2<f32> {
+    Vector2::new(90.0, 230.0)
+}
+
+fn default_wall_jump_lockout() -> f32 {
+    0.15
+}
+
+fn default_climb_jump_sideways() -> f32 {
+    80.0
+}
+
+fn default_rope_swing_accel() -> f32 {
+    6.0
+}
+
+fn default_rope_swing_damping() -> f32 {
+    0.5
+}
+
+fn default_rope_max_swing() -> f32 {
+    1.0
+}
+
+fn default_drop_through_time() -> f32 {
+    0.25
+}
+
+fn default_invulnerable_time() -> f32 {
+    1.5
+}
+
+fn default_knockback_speed() -> Vector2<f32> {
+    Vector2::new(60.0, 120.0)
+}
+
+fn default_knockback_time() -> f32 {
+    0.3
+}
+
+fn default_lives() -> u32 {
+    3
+}
+
+fn default_death_time() -> f32 {
+    1.0
 }
 
 impl Default for PlayerConfig {
@@ -57,54 +183,105 @@ impl Default for PlayerConfig {
             jump_speed_running: 90.0,
             jump_speed_climbing: 60.0,
             fall_accel: 22.0,
+            coyote_time: default_coyote_time(),
+            jump_buffer_time: default_jump_buffer_time(),
+            jump_cut_multiplier: default_jump_cut_multiplier(),
+            wall_slide_speed: default_wall_slide_speed(),
+            wall_jump_speed: default_wall_jump_speed(),
+            wall_jump_lockout: default_wall_jump_lockout(),
+            climb_jump_sideways: default_climb_jump_sideways(),
+            rope_swing_accel: default_rope_swing_accel(),
+            rope_swing_damping: default_rope_swing_damping(),
+            rope_max_swing: default_rope_max_swing(),
+            drop_through_time: default_drop_through_time(),
+            layers: CollisionLayers::player(),
+            invulnerable_time: default_invulnerable_time(),
+            knockback_speed: default_knockback_speed(),
+            knockback_time: default_knockback_time(),
+            lives: default_lives(),
+            death_time: default_death_time(),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum PlayerAnimationState {
-    Standing,
-    Walking,
-    Crouching,
-    Running,
-    Stopping,
-    Jumping,
-    Falling,
-    Damaged,
-    Dying,
-    Climbing,
+/// Where the player enters the level.
+#[derive(Debug, Copy, Clone)]
+pub struct PlayerSpawn(pub Vector2<f32>);
+
+impl Default for PlayerSpawn {
+    fn default() -> Self {
+        PlayerSpawn(Vector2::new(16.0, 24.0))
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum PlayerStance {
-    Standing,
-    Crouching,
-    Climbing
+/// What the player keeps when moving on to the next level or respawning.
+#[derive(Debug, Clone, Default)]
+pub struct PlayerProgress {
+    pub score: usize,
+    pub money: usize,
+    pub power_up: Option<PowerUp>,
+    pub lives: u32,
 }
 
 #[derive(Debug)]
 pub struct Player {
     pub config: PlayerConfig,
     pub game_counter: u32,
-    pub animation_state: PlayerAnimationState,
+    pub state: PlayerState,
+    /// Seconds since entering the current state.
+    pub state_time: f32,
 
     // Set by [InteractiveItemSystem, EnemySystem]
     pub money: usize,
     pub score: usize,
     pub power_up: Option<PowerUp>,
 
+    // Set by ClimbableSystem
+    pub climbable: Option<ClimbContact>,
+    /// Angle of the rope being swung on from hanging straight down, in radians.
+    pub swing_angle: f32,
+    pub swing_speed: f32,
+
     // Set by GroundSystem
-    pub stance: PlayerStance,
     pub on_ground: bool,
+    /// Only standing on one-way platforms, so the player can drop through.
+    pub on_one_way: bool,
+    /// Rise over run of the slope under the player, zero on flat ground.
+    pub ground_slope: f32,
+    /// Velocity of the moving platform under the player.
+    pub ground_velocity: Vector2<f32>,
     pub blocked: bool,
+    /// The side of the wall the player is touching, -1.0 for left and 1.0 for right.
+    pub wall_side: Option<f32>,
+    pub drop_timer: f32,
+    /// Counts down after leaving the ground. The player can still jump until it runs out.
+    pub coyote_timer: f32,
+    /// Counts down after a wall jump. Steering is ignored until it runs out.
+    pub lockout_timer: f32,
+    /// 1.0 facing right, -1.0 facing left.
+    pub facing: f32,
+
+    // Set by AbilitySystem
+    /// Holding on to the wall with the KiClaws, which keeps a wall slide going without
+    /// steering into the wall.
+    pub clinging: bool,
+
+    // Set by PlayerDamageSystem
+    pub lives: u32,
+    pub invulnerable_timer: f32,
 
     // Set by PlayerMovementSystem
     pub intent: Vector2<f32>,
-    pub running: bool,
-    pub jumping: bool,
+    pub run_held: bool,
+    pub jump_held: bool,
     pub jump_edge: bool,
+    /// Counts down after a jump press. The press jumps as soon as it can until it runs out.
+    pub jump_buffer_timer: f32,
 }
 
+/// Shortest distance the player hangs below the top of a rope.
+const ROPE_MIN_LENGTH: f32 = 8.0;
+
 lazy_static! {
     static ref STANDING_BBOX: BoundingBox2D = {
         BoundingBox2D {
@@ -138,92 +315,232 @@ impl Player {
         Player {
             config,
             game_counter: 0,
-            animation_state: PlayerAnimationState::Standing,
+            state: PlayerState::Idle,
+            state_time: 0.0,
             money: 0,
             score: 0,
             power_up: None,
-            stance: PlayerStance::Standing,
+            climbable: None,
+            swing_angle: 0.0,
+            swing_speed: 0.0,
             on_ground: true,
+            on_one_way: false,
+            ground_slope: 0.0,
+            ground_velocity: Vector2::new(0.0, 0.0),
             blocked: false,
+            wall_side: None,
+            drop_timer: 0.0,
+            coyote_timer: 0.0,
+            lockout_timer: 0.0,
+            facing: 1.0,
+            clinging: false,
+            lives: config.lives,
+            invulnerable_timer: 0.0,
             intent: Vector2::new(0.0, 0.0),
-            running: false,
-            jumping: false,
+            run_held: false,
+            jump_held: false,
             jump_edge: false,
+            jump_buffer_timer: 0.0,
+        }
+    }
+
+    /// Which way the player is steering, ignored for a moment after a wall jump.
+    pub fn steering(&self) -> f32 {
+        if self.lockout_timer > 0.0 {
+            0.0
+        } else {
+            self.intent.x
+        }
+    }
+
+    /// Remembers jump presses and how recently the player stood on the ground.
+    pub fn update_jump_timers(&mut self, time_step: f32) {
+        self.coyote_timer = if self.state.is_grounded() && self.on_ground {
+            self.config.coyote_time
+        } else {
+            (self.coyote_timer - time_step).max(0.0)
+        };
+        self.jump_buffer_timer = if self.jump_edge {
+            self.config.jump_buffer_time
+        } else {
+            (self.jump_buffer_timer - time_step).max(0.0)
+        };
+    }
+
+    /// Takes whichever transition the table in `player_state` calls for.
+    pub fn update_state(&mut self, physics: &mut PhysicsBox) {
+        if let Some(next) = next_state(self, physics) {
+            self.transition(next, physics);
+        }
+    }
+
+    /// Leaves the current state for `to`, running the exit and enter hooks.
+    pub fn transition(&mut self, to: PlayerState, physics: &mut PhysicsBox) {
+        let from = self.state;
+        debug!("Player state {:?} -> {:?}", from, to);
+        self.exit(to, physics);
+        self.state = to;
+        self.state_time = 0.0;
+        self.enter(from, physics);
+    }
+
+    fn enter(&mut self, from: PlayerState, physics: &mut PhysicsBox) {
+        match self.state {
+            PlayerState::Jump => self.jump(from, physics),
+            PlayerState::Climb => {
+                // Carry the player's momentum into the swing of a rope, starting from wherever
+                // along it they grabbed.
+                if let Some(contact) = self.climbable.filter(|c| c.kind == ClimbableKind::Rope) {
+                    let length = contact.length.max(ROPE_MIN_LENGTH);
+                    self.swing_angle = (-contact.offset_x / length).max(-1.0).min(1.0).asin();
+                    self.swing_speed = physics.velocity.x / length;
+                } else {
+                    self.swing_angle = 0.0;
+                    self.swing_speed = 0.0;
+                }
+                physics.velocity = Vector2::new(0.0, 0.0);
+            }
+            PlayerState::Hurt => {
+                self.on_ground = false;
+                self.invulnerable_timer = self.config.invulnerable_time;
+            }
+            PlayerState::Dead => {
+                self.lives = self.lives.saturating_sub(1);
+                physics.velocity = Vector2::new(0.0, self.config.knockback_speed.y);
+            }
+            _ => {}
         }
     }
 
-    pub fn maybe_jump(&mut self, physics: &mut PhysicsBox) {
-        // Don't jump again if the player is holding jump.
-        if !self.jump_edge || self.blocked {
+    fn exit(&mut self, to: PlayerState, physics: &mut PhysicsBox) {
+        match self.state {
+            // Climbing speed doesn't carry over once the player lets go.
+            PlayerState::Climb if to != PlayerState::Jump => {
+                physics.velocity = Vector2::new(0.0, 0.0);
+            }
+            // Letting go of jump on the way up cuts the jump short.
+            PlayerState::Jump if to == PlayerState::Fall && physics.velocity.y > 0.0 => {
+                physics.velocity.y *= self.config.jump_cut_multiplier;
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves the player as their current state calls for.
+    pub fn act(&mut self, physics: &mut PhysicsBox, time_step: f32) {
+        match self.state {
+            PlayerState::Idle | PlayerState::Stop => self.ground_slide(physics, time_step),
+            PlayerState::Walk => self.ground_move(physics, false, time_step),
+            PlayerState::Run => self.ground_move(physics, true, time_step),
+            PlayerState::Crouch => {
+                if self.intent.x == 0.0 {
+                    self.ground_slide(physics, time_step);
+                } else {
+                    self.ground_move(physics, false, time_step);
+                }
+            }
+            PlayerState::Jump | PlayerState::Fall => self.fall(physics, time_step),
+            PlayerState::WallSlide => self.wall_slide(physics, time_step),
+            PlayerState::Climb => self.climb_move(physics, time_step),
+            PlayerState::Hurt | PlayerState::Dead => self.tumble(physics, time_step),
+        }
+    }
+
+    /// Launches the player into a jump, using up the buffered press. Without a press to use
+    /// up, whatever sent the player upwards has already set their velocity.
+    fn jump(&mut self, from: PlayerState, physics: &mut PhysicsBox) {
+        let pressed = self.jump_buffer_timer > 0.0;
+        self.jump_buffer_timer = 0.0;
+        self.coyote_timer = 0.0;
+        if !pressed {
             return;
         }
-        self.animation_state = PlayerAnimationState::Jumping;
-        self.jumping = true;
-        if self.running
+        if from == PlayerState::Climb {
+            // Letting go of a rope keeps the speed of the swing.
+            let swing = match self.climbable {
+                Some(contact) if contact.kind == ClimbableKind::Rope => {
+                    self.swing_speed * contact.length.max(ROPE_MIN_LENGTH) * self.swing_angle.cos()
+                }
+                _ => 0.0,
+            };
+            physics.velocity.x = self.intent.x * self.config.climb_jump_sideways + swing;
+            physics.velocity.y = self.config.jump_speed_climbing;
+        } else if from == PlayerState::WallSlide {
+            let side = self.wall_side.unwrap_or(-self.facing);
+            physics.velocity = Vector2::new(
+                -side * self.config.wall_jump_speed.x,
+                self.config.wall_jump_speed.y,
+            );
+            self.facing = -side;
+            self.lockout_timer = self.config.wall_jump_lockout;
+        } else if self.run_held
             && self.intent.x.signum() == physics.velocity.x.signum()
             && physics.velocity.x.abs() >= self.config.min_running_jump_speed
         {
             physics.velocity.y = self.config.jump_speed_running;
-        } else if self.on_ground {
+        } else {
             physics.velocity.y = self.config.jump_speed_walking;
-        } else if self.stance == PlayerStance::Climbing {
-            self.stance = PlayerStance::Standing;
-            physics.velocity.x = self.intent.x * self.config.accel_climbing;
-            physics.velocity.y = self.config.jump_speed_climbing;
         }
     }
 
+    /// Holding down and pressing jump on a one-way platform drops through it, using up the jump
+    /// press.
+    pub fn maybe_drop(&mut self, physics: &mut PhysicsBox) {
+        if !self.jump_edge || !self.on_one_way || self.intent.y >= 0.0 {
+            return;
+        }
+        self.drop_timer = self.config.drop_through_time;
+        self.on_ground = false;
+        self.jump_edge = false;
+        self.jump_buffer_timer = 0.0;
+        physics.drop_through = true;
+    }
+
     pub fn fall(&mut self, physics: &mut PhysicsBox, time_step: f32) {
         physics.velocity.y = accelerate1d(physics.velocity.y, -self.config.fall_accel, time_step)
             .max(-self.config.max_speed_falling);
         // Air control
         physics.velocity.x = accelerate1d(
             physics.velocity.x,
-            self.intent.x * self.config.max_speed_walking,
+            self.steering() * self.config.max_speed_walking,
             time_step,
         )
         .min(self.config.max_speed_running)
         .max(-self.config.max_speed_running);
-        if physics.velocity.y < 0.0 {
-            self.animation_state = if self.stance == PlayerStance::Crouching {
-                PlayerAnimationState::Crouching
-            } else {
-                PlayerAnimationState::Falling
-            };
+    }
+
+    /// Slides down the wall at a capped speed, facing away from it.
+    pub fn wall_slide(&mut self, physics: &mut PhysicsBox, time_step: f32) {
+        physics.velocity.y = accelerate1d(physics.velocity.y, -self.config.fall_accel, time_step)
+            .max(-self.config.wall_slide_speed);
+        if let Some(side) = self.wall_side {
+            self.facing = -side;
         }
     }
 
     pub fn ground_slide(&mut self, physics: &mut PhysicsBox, time_step: f32) {
         physics.velocity.x = decelerate1d(physics.velocity.x, self.config.decel_ground, time_step);
-        self.animation_state = if self.stance == PlayerStance::Crouching {
-            PlayerAnimationState::Crouching
-        } else {
-            if physics.velocity.x.abs() < MINIMUM_CLIP {
-                PlayerAnimationState::Standing
-            } else {
-                PlayerAnimationState::Stopping
-            }
-        };
     }
 
-    pub fn ground_move(&mut self, physics: &mut PhysicsBox, time_step: f32) {
+    pub fn ground_move(&mut self, physics: &mut PhysicsBox, running: bool, time_step: f32) {
         // If the player is trying to change directions, use the ground decel as
         // assistance.
-        let (base_accel, max_speed) = if self.running {
-            self.animation_state = if self.stance == PlayerStance::Crouching {
-                PlayerAnimationState::Crouching
-            } else {
-                PlayerAnimationState::Running
-            };
+        let (base_accel, max_speed) = if running {
             (self.config.accel_running, self.config.max_speed_running)
         } else {
-            self.animation_state = if self.stance == PlayerStance::Crouching {
-                PlayerAnimationState::Crouching
-            } else {
-                PlayerAnimationState::Walking
-            };
             (self.config.accel_walking, self.config.max_speed_walking)
         };
+        // Uphill is slower and downhill faster, scaled by the cosine of the slope angle.
+        let max_speed = if self.ground_slope == 0.0 {
+            max_speed
+        } else {
+            let cos = self.ground_slope.atan().cos();
+            if self.ground_slope.signum() == self.intent.x.signum() {
+                max_speed * cos
+            } else {
+                max_speed * (2.0 - cos)
+            }
+        };
         let accel = if self.intent.x.signum() != physics.velocity.x.signum() {
             base_accel + self.config.decel_ground
         } else {
@@ -234,55 +551,57 @@ impl Player {
             .min(max_speed);
     }
 
-    pub fn run(&mut self) {
-        if self.on_ground {
-            self.running = true;
-        }
-    }
-
     pub fn reset_frame(&mut self) {
         self.intent = Vector2::new(0.0, 0.0);
-        self.running = false;
+        self.run_held = false;
         self.jump_edge = false;
     }
 
-    pub fn climb(&mut self) {
-        // Refuse to climb if holding jump.
-        if self.jumping {
-            return;
-        }
-        self.stance = PlayerStance::Climbing;
-    }
-
+    /// Climbs up and down, held to the middle of a ladder or swinging on a rope.
     pub fn climb_move(&mut self, physics: &mut PhysicsBox, time_step: f32) {
-        if self.intent.y == 0.0 && self.intent.x == 0.0 {
-            physics.velocity.x = 0.0;
-            physics.velocity.y = 0.0;
-            return;
-        }
-
-        self.animation_state = PlayerAnimationState::Climbing;
-        physics.velocity.x = accelerate1d(physics.velocity.x, self.intent.x * self.config.accel_climbing, time_step)
-            .max(-self.config.max_speed_climbing)
-            .min(self.config.max_speed_climbing);
-        physics.velocity.y = accelerate1d(physics.velocity.y, self.intent.y * self.config.accel_climbing, time_step)
-            .max(-self.config.max_speed_climbing)
-            .min(self.config.max_speed_climbing);
-    }
-
-    pub fn initial_stance(&mut self) {
-        let intends_crouch = (self.on_ground || self.stance == PlayerStance::Crouching) && self.intent.y < 0.0;
-        if self.stance == PlayerStance::Climbing {
-        } else if self.blocked || intends_crouch {
-            self.stance = PlayerStance::Crouching;
-            self.running = false;
+        let contact = match self.climbable {
+            Some(contact) => contact,
+            None => return,
+        };
+        physics.velocity.y = if self.intent.y == 0.0 {
+            0.0
         } else {
-            self.stance = PlayerStance::Standing;
+            accelerate1d(physics.velocity.y, self.intent.y * self.config.accel_climbing, time_step)
+                .max(-self.config.max_speed_climbing)
+                .min(self.config.max_speed_climbing)
+        };
+
+        // Snap to where the player should be across the climbable within this step.
+        let (offset_x, top_limit) = match contact.kind {
+            ClimbableKind::Ladder => (contact.offset_x, contact.to_top),
+            ClimbableKind::Rope => {
+                let length = contact.length.max(ROPE_MIN_LENGTH);
+                self.swing(length, time_step);
+                let offset_x = contact.offset_x + self.swing_angle.sin() * length;
+                // Hands at the top of a rope, rather than feet.
+                (offset_x, contact.length - ROPE_MIN_LENGTH)
+            }
+        };
+        physics.velocity.x = offset_x / time_step;
+        physics.velocity.y = physics.velocity.y.min(top_limit.max(0.0) / time_step);
+    }
+
+    /// Swings like a pendulum hanging `length` below the top of the rope, pushed by steering.
+    fn swing(&mut self, length: f32, time_step: f32) {
+        let gravity = -self.config.fall_accel / length * self.swing_angle.sin();
+        let push = self.intent.x * self.config.rope_swing_accel;
+        self.swing_speed += (gravity + push) * time_step;
+        self.swing_speed *= (1.0 - self.config.rope_swing_damping * time_step).max(0.0);
+        self.swing_angle += self.swing_speed * time_step;
+        if self.swing_angle.abs() > self.config.rope_max_swing {
+            self.swing_angle = self.swing_angle.signum() * self.config.rope_max_swing;
+            self.swing_speed = 0.0;
         }
     }
 
+    /// Crouching, or squeezed into a space too low to stand in.
     pub fn update_bounding_box(&self, physics: &mut PhysicsBox) {
-        physics.bbox = if self.stance == PlayerStance::Crouching {
+        physics.bbox = if self.state == PlayerState::Crouch || self.blocked {
             *CROUCHING_BBOX
         } else {
             *STANDING_BBOX
@@ -301,16 +620,68 @@ impl Player {
                         self.power_up = Some(PowerUp::KiArmor);
                     }
                 }
-                self.animation_state = PlayerAnimationState::Damaged;
                 true
             }
-            None => {
-                self.animation_state = PlayerAnimationState::Dying;
-                false
-            }
+            None => false,
         }
     }
 
+    /// Damage the player unless they were hurt recently, knocking them away from `source_x`.
+    /// Returns true if the player was knocked back.
+    pub fn hurt(&mut self, physics: &mut PhysicsBox, source_x: f32, player_x: f32) -> bool {
+        if self.is_dying() || self.invulnerable_timer > 0.0 {
+            return false;
+        }
+        if !self.damage() {
+            self.transition(PlayerState::Dead, physics);
+            return false;
+        }
+        self.transition(PlayerState::Hurt, physics);
+        let direction = if player_x < source_x { -1.0 } else { 1.0 };
+        physics.velocity = Vector2::new(
+            direction * self.config.knockback_speed.x,
+            self.config.knockback_speed.y,
+        );
+        true
+    }
+
+    pub fn is_dying(&self) -> bool {
+        self.state == PlayerState::Dead
+    }
+
+    /// The death animation has finished and the player should respawn.
+    pub fn is_dead(&self) -> bool {
+        self.is_dying() && self.state_time >= self.config.death_time
+    }
+
+    /// Fall without any control, as when knocked back or dying.
+    pub fn tumble(&mut self, physics: &mut PhysicsBox, time_step: f32) {
+        if self.on_ground && physics.velocity.y <= 0.0 {
+            physics.velocity.x =
+                decelerate1d(physics.velocity.x, self.config.decel_ground, time_step);
+        } else {
+            physics.velocity.y =
+                accelerate1d(physics.velocity.y, -self.config.fall_accel, time_step)
+                    .max(-self.config.max_speed_falling);
+        }
+    }
+
+    pub fn progress(&self) -> PlayerProgress {
+        PlayerProgress {
+            score: self.score,
+            money: self.money,
+            power_up: self.power_up,
+            lives: self.lives,
+        }
+    }
+
+    pub fn restore(&mut self, progress: &PlayerProgress) {
+        self.score = progress.score;
+        self.money = progress.money;
+        self.power_up = progress.power_up;
+        self.lives = progress.lives;
+    }
+
     pub fn collect(&mut self, power_up: PowerUp) {
         match power_up {
             PowerUp::KiArmor => {
@@ -332,23 +703,34 @@ impl Component for Player {
 pub fn initialize_player(
     world: &mut World,
     sprite_sheet: Handle<SpriteSheet>,
+    prefab: Handle<Prefab<SpritePrefab>>,
     player_start: Vector2<f32>,
 ) {
     let config = *world.read_resource::<PlayerConfig>();
+    let mut player = Player::new(config);
+    if let Some(progress) = world.try_fetch::<PlayerProgress>() {
+        player.restore(&progress);
+    }
 
     let sprite_render = SpriteRender {
         sprite_sheet: sprite_sheet,
         sprite_number: 0,
     };
 
-    let physics_box = PhysicsBox::new(*STANDING_BBOX);
+    let physics_box = PhysicsBox::new(*STANDING_BBOX).with_layers(config.layers);
     let mut transform = Transform::default();
     transform.set_translation_xyz(player_start.x, player_start.y, 0.0);
     world
         .create_entity()
         .with(sprite_render)
-        .with(Player::new(config))
+        .with(prefab)
+        .with(AnimationPlayback::default())
+        .with(player)
+        .with(Abilities::default())
+        .with(Attacker::default())
+        .with(Hurtbox::default())
         .with(physics_box)
+        .with(SimulationPosition::new(*transform.translation()))
         .with(transform)
         .build();
 }
@@ -365,9 +747,7 @@ impl<'s> System<'s> for PlayerInputSystem {
         for player in (&mut players).join() {
             player.reset_frame();
 
-            if input.action_is_down("run").unwrap_or(false) {
-                player.run();
-            }
+            player.run_held = input.action_is_down("run").unwrap_or(false);
             if let Some(mv_x_axis) = input.axis_value("x") {
                 player.intent.x = mv_x_axis;
             }
@@ -375,27 +755,35 @@ impl<'s> System<'s> for PlayerInputSystem {
                 player.intent.y = mv_y_axis;
             }
             let jump_down = input.action_is_down("jump").unwrap_or(false);
-            player.jump_edge = jump_down && !player.jumping;
-            player.jumping = jump_down;
+            player.jump_edge = jump_down && !player.jump_held;
+            player.jump_held = jump_down;
             debug!("intent: {:?}", player.intent);
         }
     }
 }
 
+/// Invulnerable players blink this many times a second.
+const FLICKER_RATE: f32 = 10.0;
+
+/// Blinks the player while they're invulnerable. Which sprite is shown is up to the
+/// PlayerAnimationSystem.
 pub struct PlayerSpriteSystem;
 
 impl<'s> System<'s> for PlayerSpriteSystem {
-    type SystemData = (ReadStorage<'s, Player>, WriteStorage<'s, SpriteRender>);
+    type SystemData = (
+        Entities<'s>,
+        ReadStorage<'s, Player>,
+        WriteStorage<'s, Hidden>,
+    );
 
-    fn run(&mut self, (players, mut renders): Self::SystemData) {
-        for (player, render) in (&players, &mut renders).join() {
-            match player.animation_state {
-                PlayerAnimationState::Crouching => {
-                    render.sprite_number = 1;
-                }
-                _ => {
-                    render.sprite_number = 0;
-                }
+    fn run(&mut self, (entities, players, mut hidden): Self::SystemData) {
+        for (entity, player) in (&entities, &players).join() {
+            let visible = player.invulnerable_timer <= 0.0
+                || (player.invulnerable_timer * FLICKER_RATE) as u32 % 2 == 0;
+            if visible {
+                hidden.remove(entity);
+            } else if !hidden.contains(entity) {
+                hidden.insert(entity, Hidden).ok();
             }
         }
     }
@@ -407,38 +795,120 @@ impl<'s> System<'s> for PlayerVelocitySystem {
     type SystemData = (
         WriteStorage<'s, Player>,
         WriteStorage<'s, PhysicsBox>,
-        Read<'s, Time>,
+        Read<'s, FixedTimestep>,
     );
 
-    fn run(&mut self, (mut players, mut physics_box, time): Self::SystemData) {
-        let time_step = time.delta_seconds();
+    fn run(&mut self, (mut players, mut physics_box, timestep): Self::SystemData) {
+        let time_step = timestep.step;
         for (player, physics) in (&mut players, &mut physics_box).join() {
-            player.initial_stance();
             player.game_counter += 1;
 
-            if player.on_ground && player.stance == PlayerStance::Climbing && player.intent.y < 0.0 {
-                player.stance = PlayerStance::Standing;
-                continue;
-            }
-            player.maybe_jump(physics);
+            player.state_time += time_step;
+            player.drop_timer = (player.drop_timer - time_step).max(0.0);
+            physics.drop_through = player.drop_timer > 0.0;
+            player.invulnerable_timer = (player.invulnerable_timer - time_step).max(0.0);
+            player.lockout_timer = (player.lockout_timer - time_step).max(0.0);
 
-            if player.stance == PlayerStance::Climbing {
-                player.climb_move(physics, time_step);
-                continue;
+            if player.state.has_control() && player.steering() != 0.0 {
+                player.facing = player.steering().signum();
+            }
+            if player.state.is_grounded() {
+                player.maybe_drop(physics);
             }
+            player.update_jump_timers(time_step);
 
-            if player.on_ground {
-                if player.intent.x == 0.0 {
-                    player.ground_slide(physics, time_step);
-                } else {
-                    player.ground_move(physics, time_step);
+            player.update_state(physics);
+            player.act(physics, time_step);
+            player.update_bounding_box(physics);
+            debug!("Move: {:?}\nPlayer: {:?}", physics, player);
+        }
+    }
+}
+
+/// Hurts players touching enemies or hazards, hit by projectiles, or crushed by moving
+/// platforms.
+#[derive(Default)]
+pub struct PlayerDamageSystem {
+    reader: Option<ReaderId<HitEvent>>,
+}
+
+impl<'s> System<'s> for PlayerDamageSystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, Player>,
+        WriteStorage<'s, PhysicsBox>,
+        ReadStorage<'s, Transform>,
+        Read<'s, SpatialHash>,
+        Read<'s, EventChannel<HitEvent>>,
+        Write<'s, CameraEffects>,
+        Read<'s, CameraConfig>,
+    );
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.reader = Some(world.fetch_mut::<EventChannel<HitEvent>>().register_reader());
+    }
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut players,
+            mut physics_boxes,
+            transforms,
+            hash,
+            hits,
+            mut camera_effects,
+            camera_config,
+        ): Self::SystemData,
+    ) {
+        let reader = self.reader.as_mut().expect("PlayerDamageSystem::setup was not called");
+        let mut hurt = false;
+        for hit in hits.read(reader) {
+            if let (Some(player), Some(physics), Some(transform)) = (
+                players.get_mut(hit.target),
+                physics_boxes.get_mut(hit.target),
+                transforms.get(hit.target),
+            ) {
+                let player_x = physics.bbox.corners.x_midpoint() + transform.translation().x;
+                if player.hurt(physics, hit.source.x, player_x) {
+                    hurt = true;
+                    if let Some(knockback) = hit.knockback {
+                        physics.velocity = knockback;
+                    }
                 }
-                player.update_bounding_box(physics);
-            }  else {
-                player.fall(physics, time_step);
-                player.update_bounding_box(physics);
             }
-            debug!("Move: {:?}\nPlayer: {:?}", physics, player);
+        }
+
+        for (entity, player, physics, transform) in
+            (&entities, &mut players, &mut physics_boxes, &transforms).join()
+        {
+            let position = Vector2::new(transform.translation().x, transform.translation().y);
+            let player_box = physics.bbox.translate(position);
+            let player_x = player_box.corners.x_midpoint();
+
+            if physics.crushed {
+                physics.crushed = false;
+                hurt |= player.hurt(physics, player_x, player_x);
+            }
+
+            let source = hash
+                .query_aabb(&player_box)
+                .into_iter()
+                .filter(|collider| collider.entity != entity)
+                .filter(|collider| physics.layers.interacts_with(&collider.layers))
+                .find(|collider| {
+                    collider.layers.membership.contains(Layer::Enemy)
+                        || collider.layers.membership.contains(Layer::Hazard)
+                })
+                .map(|collider| collider.bbox.corners.x_midpoint());
+            if let Some(source_x) = source {
+                hurt |= player.hurt(physics, source_x, player_x);
+            }
+        }
+
+        if hurt {
+            camera_effects.add_trauma(camera_config.hurt_trauma);
         }
     }
 }
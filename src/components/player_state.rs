@@ -0,0 +1,351 @@
+use super::climbable::ClimbableKind;
+use super::physics::{PhysicsBox, MINIMUM_CLIP};
+use super::player::Player;
+
+use self::PlayerState::*;
+
+/// How close the player's feet need to be to the top of a ladder to climb off it.
+const LADDER_TOP_MARGIN: f32 = 0.5;
+
+/// What the player is doing. The state decides how the player moves each step, and
+/// `TRANSITIONS` decides when they move on to another.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PlayerState {
+    Idle,
+    Walk,
+    Run,
+    /// Sliding to a halt after letting go of the direction.
+    Stop,
+    Crouch,
+    /// Rising after a jump.
+    Jump,
+    Fall,
+    /// Sliding down a wall while pushing into it.
+    WallSlide,
+    Climb,
+    /// Knocked back and out of control.
+    Hurt,
+    Dead,
+}
+
+impl PlayerState {
+    pub fn is_grounded(self) -> bool {
+        GROUNDED.contains(&self)
+    }
+
+    /// Whether the player can act. Hurt and dead players tumble until they recover.
+    pub fn has_control(self) -> bool {
+        match self {
+            Hurt | Dead => false,
+            _ => true,
+        }
+    }
+}
+
+/// Decides whether a transition should be taken this step.
+pub type Guard = fn(&Player, &PhysicsBox) -> bool;
+
+pub struct Transition {
+    pub from: &'static [PlayerState],
+    pub to: PlayerState,
+    pub when: Guard,
+}
+
+const GROUNDED: &[PlayerState] = &[Idle, Walk, Run, Stop, Crouch];
+
+/// Every way the player moves between states, in priority order. The first transition out of
+/// the current state whose guard passes is taken; if that leads back to the current state the
+/// player stays put. Hurt and Dead are entered by `Player::hurt` rather than from here.
+pub const TRANSITIONS: &[Transition] = &[
+    Transition { from: &[Hurt], to: Fall, when: recovered },
+    // Climbing
+    Transition { from: &[Climb], to: Jump, when: jump_pressed },
+    Transition { from: &[Climb], to: Idle, when: climbs_off_top },
+    Transition { from: &[Climb], to: Idle, when: climbs_off_bottom },
+    Transition { from: &[Climb], to: Fall, when: off_climbable },
+    Transition {
+        from: &[Idle, Walk, Run, Stop, Crouch, Jump, Fall, WallSlide],
+        to: Climb,
+        when: grabs_climbable,
+    },
+    // Walls
+    Transition { from: &[WallSlide], to: Jump, when: jump_pressed },
+    Transition { from: &[WallSlide], to: Idle, when: landed },
+    Transition { from: &[WallSlide], to: Fall, when: off_wall },
+    Transition { from: &[Jump, Fall], to: WallSlide, when: pushing_into_wall },
+    // Leaving the ground
+    Transition { from: GROUNDED, to: Jump, when: jump_pressed },
+    Transition { from: GROUNDED, to: Fall, when: airborne },
+    // In the air
+    Transition { from: &[Fall], to: Jump, when: coyote_jump },
+    Transition { from: &[Fall], to: Jump, when: rising },
+    Transition { from: &[Jump], to: Fall, when: jump_released },
+    Transition { from: &[Jump], to: Fall, when: descending },
+    Transition { from: &[Jump, Fall], to: Idle, when: landed },
+    // On the ground
+    Transition { from: GROUNDED, to: Crouch, when: crouching },
+    Transition { from: GROUNDED, to: Run, when: running },
+    Transition { from: GROUNDED, to: Walk, when: walking },
+    Transition { from: &[Walk, Run, Stop, Crouch], to: Stop, when: sliding },
+    Transition { from: GROUNDED, to: Idle, when: at_rest },
+];
+
+/// The state the player should move to, or None if they should stay in their current one.
+pub fn next_state(player: &Player, physics: &PhysicsBox) -> Option<PlayerState> {
+    TRANSITIONS
+        .iter()
+        .filter(|transition| transition.from.contains(&player.state))
+        .find(|transition| (transition.when)(player, physics))
+        .map(|transition| transition.to)
+        .filter(|&to| to != player.state)
+}
+
+fn recovered(player: &Player, _: &PhysicsBox) -> bool {
+    player.state_time >= player.config.knockback_time
+}
+
+/// Jump was pressed recently, including just before landing.
+fn jump_pressed(player: &Player, _: &PhysicsBox) -> bool {
+    player.jump_buffer_timer > 0.0 && !player.blocked
+}
+
+/// Jump was pressed just after walking off a ledge.
+fn coyote_jump(player: &Player, physics: &PhysicsBox) -> bool {
+    player.coyote_timer > 0.0 && jump_pressed(player, physics)
+}
+
+fn climbs_off_top(player: &Player, _: &PhysicsBox) -> bool {
+    player.intent.y > 0.0
+        && player.climbable.map_or(false, |contact| {
+            contact.kind == ClimbableKind::Ladder && contact.to_top <= LADDER_TOP_MARGIN
+        })
+}
+
+fn climbs_off_bottom(player: &Player, _: &PhysicsBox) -> bool {
+    player.on_ground && player.intent.y < 0.0
+}
+
+fn off_climbable(player: &Player, _: &PhysicsBox) -> bool {
+    player.climbable.is_none()
+}
+
+/// Holding jump refuses to grab, so jumping off a climbable doesn't catch it again. Nor can the
+/// player grab the very top of a ladder they've just climbed off.
+fn grabs_climbable(player: &Player, _: &PhysicsBox) -> bool {
+    let grabbable = player.climbable.map_or(false, |contact| {
+        contact.kind != ClimbableKind::Ladder || contact.to_top > LADDER_TOP_MARGIN
+    });
+    grabbable && player.intent.y > 0.0 && !player.jump_held
+}
+
+fn airborne(player: &Player, _: &PhysicsBox) -> bool {
+    !player.on_ground
+}
+
+/// Launched upwards by something other than a jump, such as a wall jump. Only while jump is
+/// held, so a jump cut short stays falling.
+fn rising(player: &Player, physics: &PhysicsBox) -> bool {
+    !player.on_ground && physics.velocity.y > 0.0 && player.jump_held
+}
+
+fn jump_released(player: &Player, physics: &PhysicsBox) -> bool {
+    !player.jump_held && physics.velocity.y > 0.0
+}
+
+/// Falling while steering into a wall.
+fn pushing_into_wall(player: &Player, physics: &PhysicsBox) -> bool {
+    let steering = player.steering();
+    !player.on_ground
+        && physics.velocity.y <= 0.0
+        && steering != 0.0
+        && player.wall_side == Some(steering.signum())
+}
+
+/// Let go of the wall, steered away from it or slid past its end. Clinging holds on whatever
+/// the steering.
+fn off_wall(player: &Player, _: &PhysicsBox) -> bool {
+    let steering = player.steering();
+    match player.wall_side {
+        Some(_) if player.clinging => false,
+        Some(side) => steering == 0.0 || steering.signum() != side,
+        None => true,
+    }
+}
+
+fn descending(_: &Player, physics: &PhysicsBox) -> bool {
+    physics.velocity.y <= 0.0
+}
+
+fn landed(player: &Player, physics: &PhysicsBox) -> bool {
+    player.on_ground && physics.velocity.y <= 0.0
+}
+
+fn crouching(player: &Player, _: &PhysicsBox) -> bool {
+    player.blocked || player.intent.y < 0.0
+}
+
+fn running(player: &Player, _: &PhysicsBox) -> bool {
+    player.intent.x != 0.0 && player.run_held
+}
+
+fn walking(player: &Player, _: &PhysicsBox) -> bool {
+    player.intent.x != 0.0
+}
+
+fn sliding(_: &Player, physics: &PhysicsBox) -> bool {
+    physics.velocity.x.abs() >= MINIMUM_CLIP
+}
+
+fn at_rest(_: &Player, _: &PhysicsBox) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::{
+        core::math::Vector2,
+        ecs::{Builder, World, WorldExt},
+    };
+
+    use crate::components::climbable::ClimbContact;
+    use crate::components::physics::BoundingBox2D;
+
+    fn player(state: PlayerState) -> Player {
+        let mut player = Player::default();
+        player.state = state;
+        player
+    }
+
+    fn physics() -> PhysicsBox {
+        PhysicsBox::new(BoundingBox2D::new(Vector2::new(0.0, 0.0), 8.0, 16.0))
+    }
+
+    fn ladder(to_top: f32) -> ClimbContact {
+        ClimbContact {
+            entity: World::new().create_entity().build(),
+            kind: ClimbableKind::Ladder,
+            offset_x: 0.0,
+            to_top,
+            length: to_top,
+        }
+    }
+
+    #[test]
+    fn walking_off_a_ledge_falls() {
+        let mut player = player(Walk);
+        player.on_ground = false;
+        player.intent.x = 1.0;
+        assert_eq!(next_state(&player, &physics()), Some(Fall));
+    }
+
+    #[test]
+    fn landing_comes_to_rest() {
+        let mut player = player(Fall);
+        player.on_ground = true;
+        assert_eq!(next_state(&player, &physics()), Some(Idle));
+    }
+
+    #[test]
+    fn standing_still_stays_put() {
+        assert_eq!(next_state(&player(Idle), &physics()), None);
+    }
+
+    #[test]
+    fn earlier_transitions_take_priority() {
+        let mut player = player(Walk);
+        player.intent = Vector2::new(1.0, -1.0);
+        assert_eq!(next_state(&player, &physics()), Some(Crouch));
+    }
+
+    #[test]
+    fn recovers_from_hurt_after_the_knockback() {
+        let mut player = player(Hurt);
+        player.on_ground = false;
+        player.state_time = player.config.knockback_time / 2.0;
+        assert_eq!(next_state(&player, &physics()), None);
+
+        player.state_time = player.config.knockback_time;
+        assert_eq!(next_state(&player, &physics()), Some(Fall));
+    }
+
+    #[test]
+    fn buffered_jump_is_taken_on_landing() {
+        let mut player = player(Fall);
+        player.on_ground = true;
+        player.jump_buffer_timer = player.config.jump_buffer_time;
+        assert_eq!(next_state(&player, &physics()), Some(Idle));
+
+        player.state = Idle;
+        assert_eq!(next_state(&player, &physics()), Some(Jump));
+    }
+
+    #[test]
+    fn coyote_time_allows_a_late_jump() {
+        let mut player = player(Fall);
+        player.on_ground = false;
+        player.jump_buffer_timer = player.config.jump_buffer_time;
+        assert_eq!(next_state(&player, &physics()), None);
+
+        player.coyote_timer = player.config.coyote_time;
+        assert_eq!(next_state(&player, &physics()), Some(Jump));
+    }
+
+    #[test]
+    fn wall_slide_holds_while_steering_into_the_wall() {
+        let mut player = player(WallSlide);
+        player.on_ground = false;
+        player.wall_side = Some(1.0);
+        player.intent.x = 1.0;
+        assert_eq!(next_state(&player, &physics()), None);
+
+        player.intent.x = 0.0;
+        assert_eq!(next_state(&player, &physics()), Some(Fall));
+    }
+
+    #[test]
+    fn grabs_a_ladder_when_pressing_up() {
+        let mut player = player(Fall);
+        player.on_ground = false;
+        player.climbable = Some(ladder(20.0));
+        player.intent.y = 1.0;
+        assert_eq!(next_state(&player, &physics()), Some(Climb));
+
+        // Holding jump means the player just jumped off it.
+        player.jump_held = true;
+        assert_eq!(next_state(&player, &physics()), None);
+    }
+
+    #[test]
+    fn lets_go_once_off_the_climbable() {
+        let mut player = player(Climb);
+        player.on_ground = false;
+        assert_eq!(next_state(&player, &physics()), Some(Fall));
+    }
+
+    #[test]
+    fn climbs_off_the_top_of_a_ladder() {
+        let mut player = player(Climb);
+        player.on_ground = false;
+        player.climbable = Some(ladder(LADDER_TOP_MARGIN / 2.0));
+        player.intent.y = 1.0;
+        assert_eq!(next_state(&player, &physics()), Some(Idle));
+    }
+
+    #[test]
+    fn clinging_holds_the_wall_without_steering() {
+        let mut player = player(WallSlide);
+        player.on_ground = false;
+        player.wall_side = Some(-1.0);
+        player.clinging = true;
+        assert_eq!(next_state(&player, &physics()), None);
+
+        player.jump_buffer_timer = player.config.jump_buffer_time;
+        assert_eq!(next_state(&player, &physics()), Some(Jump));
+
+        // Sliding off the end of the wall lets go, clinging or not.
+        player.jump_buffer_timer = 0.0;
+        player.wall_side = None;
+        assert_eq!(next_state(&player, &physics()), Some(Fall));
+    }
+}
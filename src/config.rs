@@ -1,30 +1,16 @@
 use serde::{Deserialize, Serialize};
 
 use crate::components::{
-    arena::ArenaConfig,
-    camera::CameraConfig,
-    ground::GroundConfig,
-    items::ItemConfig,
-    player::PlayerConfig,
+    abilities::AbilityConfig, camera::CameraConfig, hud::HudConfig, player::PlayerConfig,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Tuning shared by every level. Level content lives in `assets/levels`.
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct NinjaForceConfig {
-    pub arena: ArenaConfig,
     pub camera: CameraConfig,
-    pub ground: GroundConfig,
-    pub items: ItemConfig,
     pub player: PlayerConfig,
-}
-
-impl Default for NinjaForceConfig {
-    fn default() -> Self {
-        Self {
-            arena: ArenaConfig::default(),
-            camera: CameraConfig::default(),
-            ground: GroundConfig::default(),
-            items: ItemConfig::default(),
-            player: PlayerConfig::default(),
-        }
-    }
+    #[serde(default)]
+    pub abilities: AbilityConfig,
+    #[serde(default)]
+    pub hud: HudConfig,
 }
@@ -0,0 +1,276 @@
+use amethyst::{
+    core::{ArcThreadPool, SystemBundle, SystemDesc, Time},
+    ecs::prelude::{Dispatcher, DispatcherBuilder, System, World, WorldExt},
+    error::Error,
+    DataDispose, DataInit,
+};
+
+/// Length of one gameplay simulation step, in seconds.
+pub const FIXED_STEP_SECONDS: f32 = 1.0 / 120.0;
+/// The most simulation steps that will be run to catch up in a single frame.
+pub const MAX_STEPS_PER_FRAME: u32 = 8;
+
+/// Accumulates frame time and hands it out to the simulation in fixed sized steps.
+#[derive(Debug, Copy, Clone)]
+pub struct FixedTimestep {
+    pub step: f32,
+    pub accumulator: f32,
+    /// How far between the last two simulation steps the current frame lies, in [0, 1).
+    pub alpha: f32,
+    pub max_steps: u32,
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self {
+            step: FIXED_STEP_SECONDS,
+            accumulator: 0.0,
+            alpha: 0.0,
+            max_steps: MAX_STEPS_PER_FRAME,
+        }
+    }
+}
+
+impl FixedTimestep {
+    /// Adds a frame's worth of time and returns how many simulation steps should be run.
+    pub fn advance(&mut self, delta_seconds: f32) -> u32 {
+        self.accumulator += delta_seconds;
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        // If the simulation fell too far behind, drop the time it couldn't catch up on rather
+        // than spiralling.
+        if steps == self.max_steps {
+            self.accumulator = self.accumulator.min(self.step);
+        }
+        self.alpha = self.accumulator / self.step;
+        steps
+    }
+}
+
+/// Freezes the simulation for a moment when a heavy hit lands.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct HitStop {
+    pub remaining: f32,
+}
+
+impl HitStop {
+    pub fn freeze(&mut self, seconds: f32) {
+        self.remaining = self.remaining.max(seconds);
+    }
+
+    /// Uses up a frame's worth of the freeze and returns whatever time is left over.
+    pub fn consume(&mut self, delta_seconds: f32) -> f32 {
+        let frozen = self.remaining.min(delta_seconds);
+        self.remaining -= frozen;
+        delta_seconds - frozen
+    }
+}
+
+/// Game data with a per-frame dispatcher for input, rendering and UI, and a fixed-timestep
+/// dispatcher for gameplay.
+pub struct NinjaForceGameData<'a, 'b> {
+    core_dispatcher: Option<Dispatcher<'a, 'b>>,
+    fixed_dispatcher: Option<Dispatcher<'a, 'b>>,
+}
+
+impl<'a, 'b> NinjaForceGameData<'a, 'b> {
+    /// Runs the gameplay systems for every fixed step which has accumulated since the last
+    /// frame, then the per-frame systems. The simulation is skipped if `simulate` is false.
+    pub fn update(&mut self, world: &mut World, simulate: bool) {
+        if simulate {
+            let steps = {
+                let delta_seconds = world.read_resource::<Time>().delta_seconds();
+                let delta_seconds = world.write_resource::<HitStop>().consume(delta_seconds);
+                world.write_resource::<FixedTimestep>().advance(delta_seconds)
+            };
+            if let Some(dispatcher) = self.fixed_dispatcher.as_mut() {
+                for _ in 0..steps {
+                    dispatcher.dispatch(world);
+                    world.maintain();
+                }
+            }
+        }
+        if let Some(dispatcher) = self.core_dispatcher.as_mut() {
+            dispatcher.dispatch(world);
+        }
+    }
+
+    /// Runs exactly one simulation step, without touching the accumulator.
+    pub fn step(&mut self, world: &mut World) {
+        if let Some(dispatcher) = self.fixed_dispatcher.as_mut() {
+            dispatcher.dispatch(world);
+            world.maintain();
+        }
+    }
+}
+
+impl DataDispose for NinjaForceGameData<'_, '_> {
+    fn dispose(&mut self, world: &mut World) {
+        if let Some(dispatcher) = self.fixed_dispatcher.take() {
+            dispatcher.dispose(world);
+        }
+        if let Some(dispatcher) = self.core_dispatcher.take() {
+            dispatcher.dispose(world);
+        }
+    }
+}
+
+pub struct NinjaForceGameDataBuilder<'a, 'b> {
+    core: DispatcherBuilder<'a, 'b>,
+    fixed: DispatcherBuilder<'a, 'b>,
+}
+
+impl<'a, 'b> Default for NinjaForceGameDataBuilder<'a, 'b> {
+    fn default() -> Self {
+        Self {
+            core: DispatcherBuilder::new(),
+            fixed: DispatcherBuilder::new(),
+        }
+    }
+}
+
+impl<'a, 'b> NinjaForceGameDataBuilder<'a, 'b> {
+    pub fn with_core_bundle<B>(mut self, world: &mut World, bundle: B) -> Result<Self, Error>
+    where
+        B: SystemBundle<'a, 'b>,
+    {
+        bundle.build(world, &mut self.core)?;
+        Ok(self)
+    }
+
+    pub fn with_core<S>(mut self, system: S, name: &str, dependencies: &[&str]) -> Self
+    where
+        for<'c> S: System<'c> + Send + 'a,
+    {
+        self.core.add(system, name, dependencies);
+        self
+    }
+
+    pub fn with_core_desc<SD, S>(
+        mut self,
+        world: &mut World,
+        desc: SD,
+        name: &str,
+        dependencies: &[&str],
+    ) -> Self
+    where
+        SD: SystemDesc<'a, 'b, S>,
+        for<'c> S: System<'c> + Send + 'a,
+    {
+        self.core.add(desc.build(world), name, dependencies);
+        self
+    }
+
+    pub fn with_fixed<S>(mut self, system: S, name: &str, dependencies: &[&str]) -> Self
+    where
+        for<'c> S: System<'c> + Send + 'a,
+    {
+        self.fixed.add(system, name, dependencies);
+        self
+    }
+}
+
+impl<'a, 'b> DataInit<NinjaForceGameData<'a, 'b>> for NinjaForceGameDataBuilder<'a, 'b> {
+    fn build(self, world: &mut World) -> NinjaForceGameData<'a, 'b> {
+        world.insert(FixedTimestep::default());
+        world.insert(HitStop::default());
+        let pool = (*world.read_resource::<ArcThreadPool>()).clone();
+
+        let mut core_dispatcher = self.core.with_pool(pool.clone()).build();
+        let mut fixed_dispatcher = self.fixed.with_pool(pool).build();
+        core_dispatcher.setup(world);
+        fixed_dispatcher.setup(world);
+
+        NinjaForceGameData {
+            core_dispatcher: Some(core_dispatcher),
+            fixed_dispatcher: Some(fixed_dispatcher),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::core::rayon::ThreadPoolBuilder;
+    use amethyst::ecs::prelude::Write;
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct Steps(u32);
+
+    struct CountSteps;
+
+    impl<'s> System<'s> for CountSteps {
+        type SystemData = Write<'s, Steps>;
+
+        fn run(&mut self, mut steps: Self::SystemData) {
+            steps.0 += 1;
+        }
+    }
+
+    fn game_data(world: &mut World) -> NinjaForceGameData<'static, 'static> {
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        world.insert::<ArcThreadPool>(Arc::new(pool));
+        world.insert(Time::default());
+        NinjaForceGameDataBuilder::default()
+            .with_fixed(CountSteps, "count_steps", &[])
+            .build(world)
+    }
+
+    fn frame(world: &mut World, delta_seconds: f32) {
+        world.write_resource::<Time>().set_delta_seconds(delta_seconds);
+    }
+
+    #[test]
+    fn step_runs_one_simulation_step() {
+        let mut world = World::new();
+        let mut data = game_data(&mut world);
+        data.step(&mut world);
+        data.step(&mut world);
+        assert_eq!(world.read_resource::<Steps>().0, 2);
+        assert_eq!(world.read_resource::<FixedTimestep>().accumulator, 0.0);
+    }
+
+    #[test]
+    fn update_runs_accumulated_steps() {
+        let mut world = World::new();
+        let mut data = game_data(&mut world);
+        frame(&mut world, FIXED_STEP_SECONDS * 3.5);
+        data.update(&mut world, true);
+        assert_eq!(world.read_resource::<Steps>().0, 3);
+    }
+
+    #[test]
+    fn update_caps_steps_per_frame() {
+        let mut world = World::new();
+        let mut data = game_data(&mut world);
+        frame(&mut world, 1.0);
+        data.update(&mut world, true);
+        assert_eq!(world.read_resource::<Steps>().0, MAX_STEPS_PER_FRAME);
+        assert!(world.read_resource::<FixedTimestep>().accumulator <= FIXED_STEP_SECONDS);
+    }
+
+    #[test]
+    fn update_without_simulating_runs_no_steps() {
+        let mut world = World::new();
+        let mut data = game_data(&mut world);
+        frame(&mut world, FIXED_STEP_SECONDS * 3.5);
+        data.update(&mut world, false);
+        assert_eq!(world.read_resource::<Steps>().0, 0);
+        assert_eq!(world.read_resource::<FixedTimestep>().accumulator, 0.0);
+    }
+
+    #[test]
+    fn hit_stop_holds_back_steps() {
+        let mut world = World::new();
+        let mut data = game_data(&mut world);
+        world.write_resource::<HitStop>().freeze(FIXED_STEP_SECONDS * 2.0);
+        frame(&mut world, FIXED_STEP_SECONDS * 3.5);
+        data.update(&mut world, true);
+        assert_eq!(world.read_resource::<Steps>().0, 1);
+        assert_eq!(world.read_resource::<HitStop>().remaining, 0.0);
+    }
+}
@@ -0,0 +1,98 @@
+use amethyst::{
+    assets::{Asset, Handle},
+    core::math::Vector2,
+    ecs::{prelude::World, VecStorage},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::components::{
+    arena::ArenaConfig,
+    camera::CameraPan,
+    climbable::ClimbablesConfig,
+    enemy::EnemiesConfig,
+    ground::GroundConfig,
+    items::ItemConfig,
+    parallax::ParallaxConfig,
+    platform::PlatformConfig,
+    player::PlayerSpawn,
+};
+use crate::tilemap::TileMap;
+
+/// The ordered list of level files, relative to the assets directory.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct LevelManifest {
+    pub levels: Vec<String>,
+}
+
+/// Everything placed in a single level. Loaded from `assets/levels/*.ron`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LevelData {
+    pub name: String,
+    #[serde(default)]
+    pub arena: ArenaConfig,
+    #[serde(default)]
+    pub ground: GroundConfig,
+    #[serde(default)]
+    pub items: ItemConfig,
+    #[serde(default)]
+    pub climbables: ClimbablesConfig,
+    #[serde(default)]
+    pub platforms: PlatformConfig,
+    #[serde(default)]
+    pub enemies: EnemiesConfig,
+    /// Background layers behind the level. Kept when the rest comes from a tilemap.
+    #[serde(default)]
+    pub parallax: ParallaxConfig,
+    #[serde(default)]
+    pub player_spawn: Option<Vector2<f32>>,
+    /// Pans shown when the level starts, such as to the goal.
+    #[serde(default)]
+    pub camera_pans: Vec<CameraPan>,
+    /// A tilemap to build the arena, ground and items from, relative to the assets directory.
+    /// Either our own RON format or a Tiled `.json`/`.tmx` export.
+    #[serde(default)]
+    pub tilemap: Option<String>,
+}
+
+impl Asset for LevelData {
+    const NAME: &'static str = "ninja_force::LevelData";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
+}
+
+impl LevelData {
+    /// Replaces the level's contents with those of its tilemap, once that has loaded.
+    pub fn apply_tilemap(&mut self, tilemap: &TileMap) {
+        self.arena = tilemap.arena_config();
+        self.ground = tilemap.ground_config();
+        self.platforms.tile_size = tilemap.tile_size();
+        self.items = tilemap.item_config();
+        self.climbables = tilemap.climbable_config();
+        self.enemies = tilemap.enemy_config();
+        if let Some(spawn) = tilemap.player_spawn() {
+            self.player_spawn = Some(spawn);
+        }
+    }
+
+    /// Makes this level's configuration available to the `initialize_*` functions.
+    pub fn insert_resources(&self, world: &mut World) {
+        world.insert(self.arena.clone());
+        world.insert(self.ground.clone());
+        world.insert(self.items.clone());
+        world.insert(self.climbables.clone());
+        world.insert(self.platforms.clone());
+        world.insert(self.enemies.clone());
+        world.insert(self.parallax.clone());
+        world.insert(self.player_spawn.map(PlayerSpawn).unwrap_or_default());
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LevelProgress {
+    /// Set once the player reaches the level's goal.
+    pub complete: bool,
+    /// Where the player restarts after dying, if they've reached a checkpoint.
+    pub checkpoint: Option<Vector2<f32>>,
+    /// Seconds spent in the level, including lives lost.
+    pub time: f32,
+}
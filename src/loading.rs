@@ -0,0 +1,343 @@
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader, Prefab, PrefabLoader, ProgressCounter, RonFormat},
+    ecs::prelude::Entity,
+    input::{is_close_requested, is_key_down, VirtualKeyCode},
+    prelude::*,
+    renderer::{
+        formats::texture::ImageFormat,
+        sprite::{SpriteSheet, SpriteSheetFormat},
+        Texture,
+    },
+    ui::{Anchor, TtfFormat, UiImage, UiLoader, UiPrefab, UiText, UiTransform},
+};
+
+use crate::components::animation::SpritePrefab;
+use crate::game_data::NinjaForceGameData;
+use crate::level::{LevelData, LevelManifest};
+use crate::menu::MainMenuState;
+use crate::state::LevelState;
+use crate::tilemap::{TileMap, TiledJsonFormat, TiledTmxFormat};
+
+const BAR_WIDTH: f32 = 400.0;
+const BAR_HEIGHT: f32 = 16.0;
+
+/// Everything a level needs loaded before it can start.
+#[derive(Clone)]
+pub struct LevelAssets {
+    pub ground: Handle<SpriteSheet>,
+    pub items: Handle<SpriteSheet>,
+    pub player: Handle<SpriteSheet>,
+    /// The player's animations.
+    pub player_prefab: Handle<Prefab<SpritePrefab>>,
+    /// The HUD's layout.
+    pub hud: Handle<UiPrefab>,
+    pub level: Handle<LevelData>,
+    /// Sprite sheets for the level's parallax layers, in order. Only known once the level
+    /// itself has loaded.
+    pub backgrounds: Vec<Handle<SpriteSheet>>,
+    /// The level's tilemap, if it has one. Also only known once the level has loaded.
+    pub tilemap: Option<Handle<TileMap>>,
+}
+
+/// Shows a progress bar while the assets for the level at `index` load, then switches to the
+/// level.
+pub struct LoadingState {
+    pub index: usize,
+    pub progress_counter: ProgressCounter,
+    assets: Option<LevelAssets>,
+    bar: Option<Entity>,
+    ui: Vec<Entity>,
+    failed: bool,
+    dependencies_requested: bool,
+}
+
+impl LoadingState {
+    pub fn new(index: usize) -> Self {
+        Self {
+            index,
+            progress_counter: ProgressCounter::new(),
+            assets: None,
+            bar: None,
+            ui: Vec::new(),
+            failed: false,
+            dependencies_requested: false,
+        }
+    }
+
+    pub fn load_sprite_sheet(
+        &mut self,
+        world: &mut World,
+        sprite: &str,
+        sprite_sheet: &str,
+    ) -> Handle<SpriteSheet> {
+        let texture_handle = {
+            let loader = world.read_resource::<Loader>();
+            let texture_storage = world.read_resource::<AssetStorage<Texture>>();
+            loader.load(
+                sprite,
+                ImageFormat::default(),
+                &mut self.progress_counter,
+                &texture_storage,
+            )
+        };
+        let loader = world.read_resource::<Loader>();
+        let sprite_sheet_store = world.read_resource::<AssetStorage<SpriteSheet>>();
+
+        loader.load(
+            sprite_sheet,
+            SpriteSheetFormat(texture_handle),
+            &mut self.progress_counter,
+            &sprite_sheet_store,
+        )
+    }
+
+    pub fn load_ui(&mut self, world: &mut World, ui: &str) -> Handle<UiPrefab> {
+        let progress_counter = &mut self.progress_counter;
+        world.exec(|loader: UiLoader<'_>| loader.load(ui, progress_counter))
+    }
+
+    pub fn load_level(&mut self, world: &mut World, path: &str) -> Handle<LevelData> {
+        let loader = world.read_resource::<Loader>();
+        let level_storage = world.read_resource::<AssetStorage<LevelData>>();
+        loader.load(path, RonFormat, &mut self.progress_counter, &level_storage)
+    }
+
+    /// Picks the format from the extension: a Tiled `.json` or `.tmx` export, or our own RON.
+    pub fn load_tilemap(&mut self, world: &mut World, path: &str) -> Handle<TileMap> {
+        let loader = world.read_resource::<Loader>();
+        let tilemap_storage = world.read_resource::<AssetStorage<TileMap>>();
+        let progress_counter = &mut self.progress_counter;
+        if path.ends_with(".json") {
+            loader.load(path, TiledJsonFormat, progress_counter, &tilemap_storage)
+        } else if path.ends_with(".tmx") {
+            loader.load(path, TiledTmxFormat, progress_counter, &tilemap_storage)
+        } else {
+            loader.load(path, RonFormat, progress_counter, &tilemap_storage)
+        }
+    }
+
+    pub fn load_prefab(&mut self, world: &mut World, prefab: &str) -> Handle<Prefab<SpritePrefab>> {
+        let progress_counter = &mut self.progress_counter;
+        world.exec(|loader: PrefabLoader<'_, SpritePrefab>| {
+            loader.load(prefab, RonFormat, progress_counter)
+        })
+    }
+
+    /// Starts loading what the level refers to, its parallax layers' sprite sheets and its
+    /// tilemap, now the level has loaded. Returns whether there was anything to load.
+    fn load_level_dependencies(&mut self, world: &mut World) -> bool {
+        let level = match &self.assets {
+            Some(assets) => world
+                .read_resource::<AssetStorage<LevelData>>()
+                .get(&assets.level)
+                .cloned()
+                .unwrap_or_default(),
+            None => return false,
+        };
+        let backgrounds: Vec<_> = level
+            .parallax
+            .layers
+            .iter()
+            .map(|layer| self.load_sprite_sheet(world, &layer.image, &layer.sheet))
+            .collect();
+        let tilemap = level.tilemap.as_ref().map(|path| self.load_tilemap(world, path));
+        let requested = !backgrounds.is_empty() || tilemap.is_some();
+        if let Some(assets) = self.assets.as_mut() {
+            assets.backgrounds = backgrounds;
+            assets.tilemap = tilemap;
+        }
+        requested
+    }
+
+    /// The loading screen's font isn't counted, so the screen can't wait on itself.
+    fn initialize_ui(&mut self, world: &mut World) {
+        let font = world.read_resource::<Loader>().load(
+            "fonts/heavy_data.ttf",
+            TtfFormat,
+            (),
+            &world.read_resource(),
+        );
+
+        let label = world
+            .create_entity()
+            .with(UiTransform::new(
+                "LoadingLabel".to_string(),
+                Anchor::Middle,
+                Anchor::Middle,
+                0., 40., 1., BAR_WIDTH, 50.,
+            ))
+            .with(UiText::new(font, "Loading".to_string(), [1., 1., 1., 1.], 40.))
+            .build();
+        let background = world
+            .create_entity()
+            .with(UiTransform::new(
+                "LoadingBarBackground".to_string(),
+                Anchor::Middle,
+                Anchor::Middle,
+                0., 0., 1., BAR_WIDTH, BAR_HEIGHT,
+            ))
+            .with(UiImage::SolidColor([0.1, 0.1, 0.15, 1.]))
+            .build();
+        // Anchored by its left edge so it grows to the right as it's widened.
+        let bar = world
+            .create_entity()
+            .with(UiTransform::new(
+                "LoadingBar".to_string(),
+                Anchor::Middle,
+                Anchor::MiddleLeft,
+                -BAR_WIDTH / 2., 0., 2., 0., BAR_HEIGHT,
+            ))
+            .with(UiImage::SolidColor([1., 1., 1., 1.]))
+            .build();
+
+        self.bar = Some(bar);
+        self.ui = vec![label, background, bar];
+    }
+
+    fn update_ui(&self, world: &mut World) {
+        let total = self.progress_counter.num_assets().max(1);
+        let fraction = self.progress_counter.num_finished() as f32 / total as f32;
+        if let Some(bar) = self.bar {
+            if let Some(transform) = world.write_storage::<UiTransform>().get_mut(bar) {
+                transform.width = BAR_WIDTH * fraction;
+            }
+        }
+    }
+
+    /// Logs each asset which failed to load and lists them on screen.
+    fn report_failures(&mut self, world: &mut World) {
+        let mut message = "Failed to load:".to_string();
+        for failure in self.progress_counter.errors() {
+            error!("Failed to load asset {}: {}", failure.asset_name, failure.error);
+            message.push(' ');
+            message.push_str(&failure.asset_name);
+        }
+        self.show_failure(world, message);
+    }
+
+    /// Replaces the label with `message`, and says how to get back to the menu.
+    fn show_failure(&mut self, world: &mut World, message: String) {
+        self.failed = true;
+        let label = match self.ui.first() {
+            Some(&label) => label,
+            None => return,
+        };
+        let font = match world.write_storage::<UiText>().get_mut(label) {
+            Some(text) => {
+                text.text = message;
+                text.color = [1., 0.3, 0.3, 1.];
+                text.font.clone()
+            }
+            None => return,
+        };
+        let hint = world
+            .create_entity()
+            .with(UiTransform::new(
+                "LoadingHint".to_string(),
+                Anchor::Middle,
+                Anchor::Middle,
+                0., -40., 1., BAR_WIDTH, 30.,
+            ))
+            .with(UiText::new(
+                font,
+                "Press Escape for the main menu".to_string(),
+                [1., 1., 1., 1.],
+                20.,
+            ))
+            .build();
+        self.ui.push(hint);
+    }
+}
+
+impl<'a, 'b> State<NinjaForceGameData<'a, 'b>, StateEvent> for LoadingState {
+    fn on_start(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        let world = data.world;
+        self.initialize_ui(world);
+
+        let path = world
+            .read_resource::<LevelManifest>()
+            .levels
+            .get(self.index)
+            .cloned();
+        let path = match path {
+            Some(path) => path,
+            None => {
+                error!("No level {} in the level manifest", self.index);
+                self.show_failure(world, format!("No level {}", self.index + 1));
+                return;
+            }
+        };
+
+        let ground = self.load_sprite_sheet(world, "sprites/dirt.png", "sprites/dirt.ron");
+        let items = self.load_sprite_sheet(world, "sprites/items.png", "sprites/items.ron");
+        let player = self.load_sprite_sheet(world, "sprites/player.png", "sprites/player.ron");
+        let player_prefab = self.load_prefab(world, "prefabs/player.ron");
+        let hud = self.load_ui(world, "ui/hud.ron");
+        let level = self.load_level(world, &path);
+        self.assets = Some(LevelAssets {
+            ground,
+            items,
+            player,
+            player_prefab,
+            hud,
+            level,
+            backgrounds: Vec::new(),
+            tilemap: None,
+        });
+    }
+
+    fn on_stop(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        data.world.delete_entities(&self.ui).ok();
+        self.ui.clear();
+        self.bar = None;
+    }
+
+    fn handle_event(
+        &mut self,
+        _: StateData<'_, NinjaForceGameData<'a, 'b>>,
+        event: StateEvent,
+    ) -> Trans<NinjaForceGameData<'a, 'b>, StateEvent> {
+        match &event {
+            StateEvent::Window(event) if is_close_requested(event) => Trans::Quit,
+            // There's nothing to wait for once loading has failed.
+            StateEvent::Window(event)
+                if self.failed && is_key_down(event, VirtualKeyCode::Escape) =>
+            {
+                Trans::Switch(Box::new(MainMenuState::default()))
+            }
+            _ => Trans::None,
+        }
+    }
+
+    fn update(
+        &mut self,
+        data: StateData<'_, NinjaForceGameData<'a, 'b>>,
+    ) -> Trans<NinjaForceGameData<'a, 'b>, StateEvent> {
+        // Nothing to simulate yet, but the asset processors and UI still need to run.
+        data.data.update(data.world, false);
+        if self.failed {
+            return Trans::None;
+        }
+
+        self.update_ui(data.world);
+        // Wait for everything to settle so every failure is reported together.
+        if self.progress_counter.is_failed() && self.progress_counter.num_loading() == 0 {
+            self.report_failures(data.world);
+            return Trans::None;
+        }
+        if !self.progress_counter.is_complete() {
+            return Trans::None;
+        }
+        if !self.dependencies_requested {
+            self.dependencies_requested = true;
+            if self.load_level_dependencies(data.world) {
+                return Trans::None;
+            }
+        }
+
+        match self.assets.take() {
+            Some(assets) => Trans::Switch(Box::new(LevelState::new(self.index, assets))),
+            None => Trans::None,
+        }
+    }
+}
@@ -6,6 +6,8 @@ extern crate log;
 extern crate lazy_static;
 
 use amethyst::{
+    animation::AnimationBundle,
+    assets::{PrefabLoaderSystemDesc, Processor},
     config::Config,
     core::{frame_limiter::FrameRateLimitStrategy, transform::TransformBundle},
     input::{InputBundle, StringBindings},
@@ -13,7 +15,7 @@ use amethyst::{
     renderer::{
         plugins::{RenderFlat2D, RenderToWindow},
         types::DefaultBackend,
-        RenderingBundle,
+        RenderingBundle, SpriteRender,
     },
     ui::{RenderUi, UiBundle},
     utils::application_root_dir,
@@ -22,10 +24,19 @@ use std::time::Duration;
 
 mod components;
 mod config;
+mod game_data;
 mod geometry;
+mod level;
+mod loading;
+mod menu;
 mod state;
+mod tilemap;
 
+use crate::components::animation::{AnimationId, SpritePrefab};
 use crate::config::NinjaForceConfig;
+use crate::game_data::NinjaForceGameDataBuilder;
+use crate::level::{LevelData, LevelManifest};
+use crate::tilemap::TileMap;
 
 fn main() -> amethyst::Result<()> {
     env_logger::init();
@@ -37,49 +48,178 @@ fn main() -> amethyst::Result<()> {
     let binding_path = config_dir.join("bindings.ron");
     let display_config_path = config_dir.join("display.ron");
     let game_config = NinjaForceConfig::load(config_dir.join("game.ron"))?;
+    let level_manifest = LevelManifest::load(assets_dir.join("levels/manifest.ron"))?;
 
     let input_bundle =
         InputBundle::<StringBindings>::new().with_bindings_from_file(binding_path)?;
 
-    let game_data = GameDataBuilder::default()
-        .with_bundle(TransformBundle::new())?
-        .with_bundle(input_bundle)?
-        .with(
+    let mut app_builder = Application::build(assets_dir, menu::TitleState::default())?
+        .with_frame_limit(
+            FrameRateLimitStrategy::SleepAndYield(Duration::from_millis(2)),
+            144,
+        )
+        .with_resource(game_config.camera)
+        .with_resource(game_config.player)
+        .with_resource(game_config.abilities)
+        .with_resource(game_config.hud)
+        .with_resource(level_manifest);
+
+    let game_data = NinjaForceGameDataBuilder::default()
+        // Gameplay, run at a fixed rate.
+        .with_fixed(
+            components::interpolation::SimulationRestoreSystem,
+            "simulation_restore_system",
+            &[],
+        )
+        .with_fixed(
             components::player::PlayerInputSystem,
             "movement_system",
-            &["input_system"],
+            &["simulation_restore_system"],
         )
-        .with(components::arena::ArenaSystem, "arena_system", &[])
-        .with(
-            components::camera::CameraMovementSystem,
-            "camera_system",
-            &[],
+        .with_fixed(
+            components::abilities::AbilityInputSystem,
+            "ability_input_system",
+            &["simulation_restore_system"],
+        )
+        .with_fixed(
+            components::arena::ArenaSystem,
+            "arena_system",
+            &["simulation_restore_system"],
+        )
+        .with_fixed(
+            components::broadphase::BroadphaseSystem,
+            "broadphase_system",
+            &["arena_system"],
         )
-        .with(
+        .with_fixed(
             components::ground::ContactPassSystem,
             "ground_system",
-            &[])
-        .with(components::score::ScoreSystem, "score_system", &[])
-        .with(
+            &["broadphase_system"])
+        .with_fixed(
             components::items::InteractableItemSystem,
             "item_system",
-            &["movement_system"])
-        .with(
+            &["movement_system", "broadphase_system"])
+        .with_fixed(
+            components::climbable::ClimbableSystem,
+            "climbable_system",
+            &["broadphase_system"],
+        )
+        .with_fixed(
+            components::enemy::EnemySystem,
+            "enemy_system",
+            &["ground_system"],
+        )
+        .with_fixed(
+            components::combat::ProjectileSystem,
+            "projectile_system",
+            &["broadphase_system"],
+        )
+        .with_fixed(
+            components::player::PlayerDamageSystem::default(),
+            "player_damage_system",
+            &["ground_system", "enemy_system", "projectile_system"],
+        )
+        .with_fixed(
             components::player::PlayerVelocitySystem,
             "player_velocity_system",
-            &["ground_system", "movement_system"],
+            &[
+                "ground_system",
+                "item_system",
+                "climbable_system",
+                "movement_system",
+                "player_damage_system",
+            ],
+        )
+        .with_fixed(
+            components::abilities::AbilitySystem,
+            "ability_system",
+            &["player_velocity_system", "ability_input_system"],
+        )
+        .with_fixed(
+            components::combat::HitResolutionSystem,
+            "hit_resolution_system",
+            &["ability_system"],
+        )
+        .with_fixed(
+            components::enemy::EnemyHitSystem::default(),
+            "enemy_hit_system",
+            &["projectile_system", "hit_resolution_system"],
         )
-        .with(
+        .with_fixed(
             components::physics::MoveExecutionSystem,
             "move_execution_system",
-            &["player_velocity_system"])
-        .with(
+            &["player_velocity_system", "enemy_system", "ability_system", "enemy_hit_system"])
+        .with_fixed(
+            components::platform::PlatformMovementSystem,
+            "platform_system",
+            &["move_execution_system"],
+        )
+        .with_fixed(
+            components::hud::LevelTimerSystem,
+            "level_timer_system",
+            &["item_system"],
+        )
+        .with_fixed(
+            components::interpolation::SimulationCaptureSystem,
+            "simulation_capture_system",
+            &["platform_system"],
+        )
+        // Presentation, run every frame.
+        .with_core_bundle(&mut app_builder.world, input_bundle)?
+        .with_core(
+            components::interpolation::TransformInterpolationSystem,
+            "transform_interpolation_system",
+            &[],
+        )
+        .with_core_bundle(
+            &mut app_builder.world,
+            TransformBundle::new().with_dep(&["transform_interpolation_system"]),
+        )?
+        .with_core(
+            components::camera::CameraMovementSystem,
+            "camera_system",
+            &["transform_interpolation_system"],
+        )
+        .with_core(
+            components::camera::CameraEffectsSystem,
+            "camera_effects_system",
+            &["camera_system"],
+        )
+        .with_core(
+            components::parallax::ParallaxSystem,
+            "parallax_system",
+            &["camera_effects_system"],
+        )
+        .with_core(Processor::<LevelData>::new(), "level_processor", &[])
+        .with_core(Processor::<TileMap>::new(), "tilemap_processor", &[])
+        .with_core(components::hud::HudSystem, "hud_system", &[])
+        .with_core(
             components::player::PlayerSpriteSystem,
             "player_sprite_system",
-            &["player_velocity_system"],
+            &[],
+        )
+        .with_core_desc(
+            &mut app_builder.world,
+            PrefabLoaderSystemDesc::<SpritePrefab>::default(),
+            "sprite_prefab_loader",
+            &[],
         )
-        .with_bundle(UiBundle::<StringBindings>::new())?
-        .with_bundle(
+        .with_core(
+            components::animation::PlayerAnimationSystem,
+            "player_animation_system",
+            &["transform_interpolation_system"],
+        )
+        .with_core_bundle(
+            &mut app_builder.world,
+            AnimationBundle::<AnimationId, SpriteRender>::new(
+                "animation_control_system",
+                "sampler_interpolation_system",
+            )
+            .with_dep(&["player_animation_system"]),
+        )?
+        .with_core_bundle(&mut app_builder.world, UiBundle::<StringBindings>::new())?
+        .with_core_bundle(
+            &mut app_builder.world,
             RenderingBundle::<DefaultBackend>::new()
                 .with_plugin(
                     RenderToWindow::from_config_path(display_config_path)?
@@ -89,17 +229,7 @@ fn main() -> amethyst::Result<()> {
                 .with_plugin(RenderUi::default()),
         )?;
 
-    let mut game = Application::build(assets_dir, state::NinjaForce::new())?
-        .with_frame_limit(
-            FrameRateLimitStrategy::SleepAndYield(Duration::from_millis(2)),
-            144,
-        )
-        .with_resource(game_config.arena)
-        .with_resource(game_config.camera)
-        .with_resource(game_config.items)
-        .with_resource(game_config.ground)
-        .with_resource(game_config.player)
-        .build(game_data)?;
+    let mut game = app_builder.build(game_data)?;
     game.run();
 
     Ok(())
@@ -0,0 +1,328 @@
+use amethyst::{
+    core::{ParentHierarchy, Time},
+    ecs::prelude::Entity,
+    input::{is_close_requested, is_key_down, VirtualKeyCode},
+    prelude::*,
+    ui::{UiCreator, UiEvent, UiEventType, UiFinder, UiText},
+};
+
+use crate::components::player::PlayerProgress;
+use crate::game_data::NinjaForceGameData;
+use crate::loading::LoadingState;
+
+type MenuTrans<'a, 'b> = Trans<NinjaForceGameData<'a, 'b>, StateEvent>;
+
+/// A screen built from a UI prefab, whose buttons are told apart by their ids.
+struct MenuScreen {
+    prefab: &'static str,
+    buttons: &'static [&'static str],
+    root: Option<Entity>,
+}
+
+impl MenuScreen {
+    fn new(prefab: &'static str, buttons: &'static [&'static str]) -> Self {
+        Self { prefab, buttons, root: None }
+    }
+
+    fn open(&mut self, world: &mut World) {
+        let prefab = self.prefab;
+        self.root = Some(world.exec(|mut creator: UiCreator<'_>| creator.create(prefab, ())));
+    }
+
+    fn close(&mut self, world: &mut World) {
+        if let Some(root) = self.root.take() {
+            let children: Vec<Entity> = world
+                .read_resource::<ParentHierarchy>()
+                .all_children_iter(root)
+                .collect();
+            world.delete_entities(&children).ok();
+            world.delete_entity(root).ok();
+        }
+    }
+
+    /// The id of the button `event` clicked, if it clicked one of ours.
+    fn clicked(&self, world: &mut World, event: &StateEvent) -> Option<&'static str> {
+        let target = match event {
+            StateEvent::Ui(UiEvent { event_type: UiEventType::Click, target }) => *target,
+            _ => return None,
+        };
+        let buttons = self.buttons;
+        world.exec(|finder: UiFinder<'_>| {
+            buttons.iter().copied().find(|&id| finder.find(id) == Some(target))
+        })
+    }
+
+    /// Returns whether the widget was there to set.
+    fn set_text(&self, world: &mut World, id: &str, text: String) -> bool {
+        let widget = world.exec(|finder: UiFinder<'_>| finder.find(id));
+        let mut texts = world.write_storage::<UiText>();
+        match widget.and_then(|widget| texts.get_mut(widget)) {
+            Some(widget) => {
+                widget.text = text;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Starts a new game from the first level.
+fn new_game<'a, 'b>(world: &mut World) -> MenuTrans<'a, 'b> {
+    world.remove::<PlayerProgress>();
+    Trans::Switch(Box::new(LoadingState::new(0)))
+}
+
+/// The first thing shown, until Enter or Space is pressed.
+pub struct TitleState {
+    screen: MenuScreen,
+}
+
+impl Default for TitleState {
+    fn default() -> Self {
+        Self { screen: MenuScreen::new("ui/title.ron", &[]) }
+    }
+}
+
+impl<'a, 'b> State<NinjaForceGameData<'a, 'b>, StateEvent> for TitleState {
+    fn on_start(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.open(data.world);
+    }
+
+    fn on_stop(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.close(data.world);
+    }
+
+    fn handle_event(
+        &mut self,
+        _: StateData<'_, NinjaForceGameData<'a, 'b>>,
+        event: StateEvent,
+    ) -> MenuTrans<'a, 'b> {
+        match &event {
+            StateEvent::Window(event) if is_close_requested(event) => Trans::Quit,
+            StateEvent::Window(event)
+                if is_key_down(event, VirtualKeyCode::Return)
+                    || is_key_down(event, VirtualKeyCode::Space) =>
+            {
+                Trans::Switch(Box::new(MainMenuState::default()))
+            }
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) -> MenuTrans<'a, 'b> {
+        data.data.update(data.world, false);
+        Trans::None
+    }
+}
+
+pub struct MainMenuState {
+    screen: MenuScreen,
+}
+
+impl Default for MainMenuState {
+    fn default() -> Self {
+        Self { screen: MenuScreen::new("ui/main_menu.ron", &["start", "options", "quit"]) }
+    }
+}
+
+impl<'a, 'b> State<NinjaForceGameData<'a, 'b>, StateEvent> for MainMenuState {
+    fn on_start(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.open(data.world);
+    }
+
+    fn on_stop(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.close(data.world);
+    }
+
+    // Hidden while the options are open, so their buttons don't sit on top of ours.
+    fn on_pause(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.close(data.world);
+    }
+
+    fn on_resume(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.open(data.world);
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, NinjaForceGameData<'a, 'b>>,
+        event: StateEvent,
+    ) -> MenuTrans<'a, 'b> {
+        if let StateEvent::Window(event) = &event {
+            if is_close_requested(event) {
+                return Trans::Quit;
+            }
+        }
+        match self.screen.clicked(data.world, &event) {
+            Some("start") => new_game(data.world),
+            Some("options") => Trans::Push(Box::new(OptionsState::default())),
+            Some("quit") => Trans::Quit,
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) -> MenuTrans<'a, 'b> {
+        data.data.update(data.world, false);
+        Trans::None
+    }
+}
+
+/// Lists the controls, pushed over the main menu.
+pub struct OptionsState {
+    screen: MenuScreen,
+}
+
+impl Default for OptionsState {
+    fn default() -> Self {
+        Self { screen: MenuScreen::new("ui/options.ron", &["back"]) }
+    }
+}
+
+impl<'a, 'b> State<NinjaForceGameData<'a, 'b>, StateEvent> for OptionsState {
+    fn on_start(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.open(data.world);
+    }
+
+    fn on_stop(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.close(data.world);
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, NinjaForceGameData<'a, 'b>>,
+        event: StateEvent,
+    ) -> MenuTrans<'a, 'b> {
+        if let StateEvent::Window(event) = &event {
+            if is_close_requested(event) {
+                return Trans::Quit;
+            }
+            if is_key_down(event, VirtualKeyCode::Escape) {
+                return Trans::Pop;
+            }
+        }
+        match self.screen.clicked(data.world, &event) {
+            Some("back") => Trans::Pop,
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) -> MenuTrans<'a, 'b> {
+        data.data.update(data.world, false);
+        Trans::None
+    }
+}
+
+/// Pushed over the level on Escape. The level is drawn but not simulated until it's popped,
+/// and game time stops so animations and camera effects hold still too.
+pub struct PauseState {
+    screen: MenuScreen,
+}
+
+impl Default for PauseState {
+    fn default() -> Self {
+        Self { screen: MenuScreen::new("ui/pause.ron", &["resume", "main_menu"]) }
+    }
+}
+
+impl<'a, 'b> State<NinjaForceGameData<'a, 'b>, StateEvent> for PauseState {
+    fn on_start(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        data.world.write_resource::<Time>().set_time_scale(0.0);
+        self.screen.open(data.world);
+    }
+
+    fn on_stop(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        data.world.write_resource::<Time>().set_time_scale(1.0);
+        self.screen.close(data.world);
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, NinjaForceGameData<'a, 'b>>,
+        event: StateEvent,
+    ) -> MenuTrans<'a, 'b> {
+        if let StateEvent::Window(event) = &event {
+            if is_close_requested(event) {
+                return Trans::Quit;
+            }
+            if is_key_down(event, VirtualKeyCode::Escape) {
+                return Trans::Pop;
+            }
+        }
+        match self.screen.clicked(data.world, &event) {
+            Some("resume") => Trans::Pop,
+            Some("main_menu") => {
+                data.world.remove::<PlayerProgress>();
+                Trans::Sequence(vec![
+                    Trans::Pop,
+                    Trans::Switch(Box::new(MainMenuState::default())),
+                ])
+            }
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) -> MenuTrans<'a, 'b> {
+        data.data.update(data.world, false);
+        Trans::None
+    }
+}
+
+/// Shown once the player runs out of lives. Retrying starts the level they lost on again
+/// with fresh lives.
+pub struct GameOverState {
+    index: usize,
+    score: usize,
+    screen: MenuScreen,
+    /// Whether the score label has been filled in. It only exists once the prefab has loaded.
+    shown_score: bool,
+}
+
+impl GameOverState {
+    pub fn new(index: usize, score: usize) -> Self {
+        Self {
+            index,
+            score,
+            screen: MenuScreen::new("ui/game_over.ron", &["retry", "main_menu"]),
+            shown_score: false,
+        }
+    }
+}
+
+impl<'a, 'b> State<NinjaForceGameData<'a, 'b>, StateEvent> for GameOverState {
+    fn on_start(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.open(data.world);
+    }
+
+    fn on_stop(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        self.screen.close(data.world);
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, NinjaForceGameData<'a, 'b>>,
+        event: StateEvent,
+    ) -> MenuTrans<'a, 'b> {
+        if let StateEvent::Window(event) = &event {
+            if is_close_requested(event) {
+                return Trans::Quit;
+            }
+        }
+        match self.screen.clicked(data.world, &event) {
+            Some("retry") => {
+                data.world.remove::<PlayerProgress>();
+                Trans::Switch(Box::new(LoadingState::new(self.index)))
+            }
+            Some("main_menu") => Trans::Switch(Box::new(MainMenuState::default())),
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) -> MenuTrans<'a, 'b> {
+        data.data.update(data.world, false);
+        if !self.shown_score {
+            let score = format!("Score: {}", self.score);
+            self.shown_score = self.screen.set_text(data.world, "score", score);
+        }
+        Trans::None
+    }
+}
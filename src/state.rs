@@ -1,105 +1,181 @@
 use amethyst::{
-    animation::AnimationSetPrefab,
-    assets::{
-        AssetStorage, Handle, Loader, Prefab, PrefabData, PrefabLoader, ProgressCounter, RonFormat,
-    },
+    assets::AssetStorage,
     core::math::Vector2,
-    ecs::prelude::Entity,
+    ecs::prelude::Join,
     error::Error,
+    input::{is_close_requested, is_key_down, VirtualKeyCode},
     prelude::*,
-    renderer::{
-        formats::texture::ImageFormat,
-        sprite::{prefab::SpriteScenePrefab, SpriteRender, SpriteSheet, SpriteSheetFormat},
-        Texture,
-    },
 };
-use serde::{Deserialize, Serialize};
 
+use crate::game_data::NinjaForceGameData;
 use crate::components::arena::initialize_arena;
-use crate::components::camera::initialize_camera;
+use crate::components::camera::{initialize_camera, CameraEffects};
+use crate::components::climbable::initialize_climbables;
+use crate::components::combat::initialize_combat;
+use crate::components::enemy::initialize_enemies;
 use crate::components::ground::initialize_ground;
+use crate::components::hud::initialize_hud;
 use crate::components::items::initialize_items;
-use crate::components::player::initialize_player;
-use crate::components::score::initialize_score;
+use crate::components::parallax::initialize_parallax;
+use crate::components::platform::initialize_platforms;
+use crate::components::player::{initialize_player, Player, PlayerProgress, PlayerSpawn};
+use crate::level::{LevelData, LevelManifest, LevelProgress};
+use crate::loading::{LevelAssets, LoadingState};
+use crate::menu::{GameOverState, MainMenuState, PauseState};
+use crate::tilemap::TileMap;
 
-/// Animation ids used in a AnimationSet
-#[derive(Eq, PartialOrd, PartialEq, Hash, Debug, Copy, Clone, Deserialize, Serialize)]
-pub enum AnimationId {
-    Move,
+/// Plays the level at `index` in the `LevelManifest`, whose assets the `LoadingState` has
+/// already loaded.
+pub struct LevelState {
+    pub index: usize,
+    assets: LevelAssets,
+    /// Set if the level couldn't be built, to quit on the next update.
+    failed: bool,
 }
 
-/// Loading data for one entity
-#[derive(Debug, Clone, Deserialize, PrefabData)]
-pub struct SpritePrefab {
-    /// Information for rendering a scene with sprites
-    sprite_scene: SpriteScenePrefab,
-    /// Аll animations that can be run on the entity
-    animation_set: AnimationSetPrefab<AnimationId, SpriteRender>,
-}
+impl LevelState {
+    pub fn new(index: usize, assets: LevelAssets) -> Self {
+        Self { index, assets, failed: false }
+    }
 
-pub struct NinjaForce {
-    pub progress_counter: Option<ProgressCounter>,
-}
+    /// Builds the level's entities, with the player at `checkpoint` if they've reached one.
+    fn start_level(&self, world: &mut World, checkpoint: Option<Vector2<f32>>) -> Result<(), Error> {
+        let mut level = world
+            .read_resource::<AssetStorage<LevelData>>()
+            .get(&self.assets.level)
+            .cloned()
+            .ok_or_else(|| Error::from_string("Level data missing after loading"))?;
+        if let Some(tilemap) = &self.assets.tilemap {
+            let tilemaps = world.read_resource::<AssetStorage<TileMap>>();
+            let tilemap = tilemaps
+                .get(tilemap)
+                .ok_or_else(|| Error::from_string("Tilemap missing after loading"))?;
+            level.apply_tilemap(tilemap);
+        }
+        info!("Starting level {}: {}", self.index + 1, level.name);
+        level.insert_resources(world);
+        world.insert(LevelProgress {
+            checkpoint,
+            ..Default::default()
+        });
+        if let Some(checkpoint) = checkpoint {
+            world.insert(PlayerSpawn(checkpoint));
+        }
 
-impl NinjaForce {
-    pub fn new() -> Self {
-        Self {
-            progress_counter: None,
+        initialize_arena(world);
+        initialize_parallax(world, &self.assets.backgrounds);
+        initialize_ground(world, self.assets.ground.clone());
+        initialize_platforms(world, self.assets.ground.clone());
+        initialize_items(world, self.assets.items.clone());
+        initialize_climbables(world, self.assets.items.clone());
+        let player_spawn = world.read_resource::<PlayerSpawn>().0;
+        initialize_player(
+            world,
+            self.assets.player.clone(),
+            self.assets.player_prefab.clone(),
+            player_spawn,
+        );
+        initialize_enemies(world, self.assets.player.clone());
+        initialize_combat(world, self.assets.items.clone());
+        initialize_camera(world);
+        // Only on the way in, not after every death.
+        if checkpoint.is_none() {
+            let mut effects = world.write_resource::<CameraEffects>();
+            for &pan in &level.camera_pans {
+                effects.pan_to(pan);
+            }
         }
+        initialize_hud(world, self.assets.hud.clone(), self.assets.items.clone());
+        Ok(())
     }
 
-    pub fn load_sprite_sheet(
-        &mut self,
-        world: &mut World,
-        sprite: &str,
-        sprite_sheet: &str,
-    ) -> Handle<SpriteSheet> {
-        let texture_handle = {
-            let loader = world.read_resource::<Loader>();
-            let texture_storage = world.read_resource::<AssetStorage<Texture>>();
-            loader.load(
-                sprite,
-                ImageFormat::default(),
-                self.progress_counter.as_mut().unwrap(),
-                &texture_storage,
-            )
-        };
-        let loader = world.read_resource::<Loader>();
-        let sprite_sheet_store = world.read_resource::<AssetStorage<SpriteSheet>>();
-
-        loader.load(
-            sprite_sheet,
-            SpriteSheetFormat(texture_handle),
-            self.progress_counter.as_mut().unwrap(),
-            &sprite_sheet_store,
-        )
+    /// The index of the level after this one, if this isn't the last.
+    fn next_level(&self, world: &World) -> Option<usize> {
+        let count = world.read_resource::<LevelManifest>().levels.len();
+        Some(self.index + 1).filter(|&next| next < count)
     }
+}
 
-    pub fn _load_prefab(&mut self, world: &mut World, prefab: &str) -> Handle<Prefab<SpritePrefab>> {
-        world.exec(|loader: PrefabLoader<'_, SpritePrefab>| {
-            loader.load(prefab, RonFormat, self.progress_counter.as_mut().unwrap())
-        })
+impl<'a, 'b> State<NinjaForceGameData<'a, 'b>, StateEvent> for LevelState {
+    fn on_start(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        if let Err(err) = self.start_level(data.world, None) {
+            error!("Failed to start level {}: {}", self.index, err);
+            self.failed = true;
+        }
     }
-}
 
-impl SimpleState for NinjaForce {
-    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
-        let world = data.world;
-        self.progress_counter = Some(Default::default());
-        let ground_sprite = self.load_sprite_sheet(world, "sprites/dirt.png", "sprites/dirt.ron");
-        let item_sprite = self.load_sprite_sheet(world, "sprites/items.png", "sprites/items.ron");
-        let player_sprite =
-            self.load_sprite_sheet(world, "sprites/player.png", "sprites/player.ron");
+    fn on_stop(&mut self, data: StateData<'_, NinjaForceGameData<'a, 'b>>) {
+        data.world.delete_all();
+    }
 
-        initialize_arena(world);
-        initialize_ground(world, ground_sprite);
-        initialize_items(world, item_sprite);
-        initialize_player(world, player_sprite, Vector2::new(16., 24.));
-        initialize_camera(world);
-        initialize_score(world);
+    fn handle_event(
+        &mut self,
+        _: StateData<'_, NinjaForceGameData<'a, 'b>>,
+        event: StateEvent,
+    ) -> Trans<NinjaForceGameData<'a, 'b>, StateEvent> {
+        match &event {
+            StateEvent::Window(event) if is_close_requested(event) => Trans::Quit,
+            StateEvent::Window(event) if is_key_down(event, VirtualKeyCode::Escape) => {
+                Trans::Push(Box::new(PauseState::default()))
+            }
+            _ => Trans::None,
+        }
     }
 
-    fn update(&mut self, _data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+    fn update(
+        &mut self,
+        data: StateData<'_, NinjaForceGameData<'a, 'b>>,
+    ) -> Trans<NinjaForceGameData<'a, 'b>, StateEvent> {
+        if self.failed {
+            return Trans::Quit;
+        }
+        data.data.update(data.world, true);
+
+        if data.world.read_resource::<LevelProgress>().complete {
+            let progress = {
+                let players = data.world.read_storage::<Player>();
+                (&players).join().next().map(Player::progress)
+            };
+            return match self.next_level(data.world) {
+                Some(next) => {
+                    if let Some(progress) = progress {
+                        data.world.insert::<PlayerProgress>(progress);
+                    }
+                    Trans::Switch(Box::new(LoadingState::new(next)))
+                }
+                None => {
+                    info!("All levels complete");
+                    data.world.remove::<PlayerProgress>();
+                    Trans::Switch(Box::new(MainMenuState::default()))
+                }
+            };
+        }
+
+        let dead = {
+            let players = data.world.read_storage::<Player>();
+            (&players).join().find(|player| player.is_dead()).map(Player::progress)
+        };
+        if let Some(progress) = dead {
+            if progress.lives == 0 {
+                info!("Game over");
+                data.world.remove::<PlayerProgress>();
+                let game_over = GameOverState::new(self.index, progress.score);
+                return Trans::Switch(Box::new(game_over));
+            }
+            // Respawn with every entity in the level reset, keeping the checkpoint and the
+            // time taken so far.
+            data.world.insert(progress);
+            let (checkpoint, time) = {
+                let level_progress = data.world.read_resource::<LevelProgress>();
+                (level_progress.checkpoint, level_progress.time)
+            };
+            data.world.delete_all();
+            if let Err(err) = self.start_level(data.world, checkpoint) {
+                error!("Failed to restart level {}: {}", self.index, err);
+                return Trans::Quit;
+            }
+            data.world.write_resource::<LevelProgress>().time = time;
+        }
         Trans::None
     }
 }
@@ -0,0 +1,640 @@
+use std::collections::HashMap;
+
+use amethyst::{
+    assets::{Asset, Format, Handle},
+    core::math::Vector2,
+    ecs::VecStorage,
+    error::Error,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::components::{
+    arena::ArenaConfig,
+    climbable::{ClimbableKind, ClimbablePosition, ClimbablesConfig},
+    enemy::{EnemiesConfig, EnemyConfig, EnemyKind},
+    ground::{GroundConfig, GroundPosition, SlopeAngle, TileShape},
+    items::{ItemConfig, ItemKind, ItemPosition},
+    physics::CollisionLayers,
+    player::PowerUp,
+};
+use crate::geometry::Corners;
+
+/// Collision for every tile drawn with a given sprite.
+#[derive(Debug, Copy, Clone, Default, Deserialize, Serialize)]
+pub struct TileProperties {
+    #[serde(default)]
+    pub shape: TileShape,
+    #[serde(default)]
+    pub one_way: bool,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+pub enum MapObjectKind {
+    PlayerSpawn,
+    Item(ItemKind),
+    Climbable(ClimbableKind),
+    Enemy,
+}
+
+/// Anything placed freely on the map rather than on the tile grid.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MapObject {
+    pub kind: MapObjectKind,
+    /// Bottom left corner in world coordinates.
+    pub position: Vector2<f32>,
+    #[serde(default)]
+    pub width: f32,
+    #[serde(default)]
+    pub height: f32,
+    #[serde(default)]
+    pub sprite_num: usize,
+}
+
+/// A level laid out on a grid of tiles.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TileMap {
+    pub tile_width: f32,
+    pub tile_height: f32,
+    pub width: usize,
+    pub height: usize,
+    /// Row major with the top row first. 0 is an empty cell, anything else is the ground sprite
+    /// number plus one.
+    pub tiles: Vec<usize>,
+    /// Keyed by ground sprite number. Tiles without properties are solid.
+    #[serde(default)]
+    pub tile_properties: HashMap<usize, TileProperties>,
+    #[serde(default)]
+    pub objects: Vec<MapObject>,
+}
+
+impl Asset for TileMap {
+    const NAME: &'static str = "ninja_force::TileMap";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
+}
+
+/// Imports a Tiled `.json` export as a `TileMap`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TiledJsonFormat;
+
+impl Format<TileMap> for TiledJsonFormat {
+    fn name(&self) -> &'static str {
+        "TiledJson"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<TileMap, Error> {
+        let tiled: TiledMap = serde_json::from_slice(&bytes)?;
+        tiled.into_tile_map()
+    }
+}
+
+/// Imports a Tiled `.tmx` export as a `TileMap`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TiledTmxFormat;
+
+impl Format<TileMap> for TiledTmxFormat {
+    fn name(&self) -> &'static str {
+        "TiledTmx"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<TileMap, Error> {
+        let text = String::from_utf8(bytes)
+            .map_err(|_| Error::from_string("TMX file is not valid UTF-8"))?;
+        TiledMap::from_tmx(&text)?.into_tile_map()
+    }
+}
+
+impl TileMap {
+    pub fn tile_size(&self) -> Vector2<f32> {
+        Vector2::new(self.tile_width, self.tile_height)
+    }
+
+    pub fn pixel_width(&self) -> f32 {
+        self.width as f32 * self.tile_width
+    }
+
+    pub fn pixel_height(&self) -> f32 {
+        self.height as f32 * self.tile_height
+    }
+
+    /// Bottom left corner of the cell in `column` and `row`, counting rows from the top.
+    pub fn cell_position(&self, column: usize, row: usize) -> Vector2<f32> {
+        Vector2::new(
+            column as f32 * self.tile_width,
+            (self.height - 1 - row) as f32 * self.tile_height,
+        )
+    }
+
+    pub fn ground_config(&self) -> GroundConfig {
+        let mut elements = Vec::new();
+        for (index, &tile) in self.tiles.iter().enumerate() {
+            if tile == 0 {
+                continue;
+            }
+            let sprite_num = tile - 1;
+            let properties = self
+                .tile_properties
+                .get(&sprite_num)
+                .cloned()
+                .unwrap_or_default();
+            elements.push(GroundPosition {
+                sprite_num,
+                pos: self.cell_position(index % self.width, index / self.width),
+                size: self.tile_size(),
+                layers: CollisionLayers::ground(),
+                one_way: properties.one_way,
+                shape: properties.shape,
+            });
+        }
+        GroundConfig { elements }
+    }
+
+    pub fn item_config(&self) -> ItemConfig {
+        let elements = self
+            .objects
+            .iter()
+            .filter_map(|object| match object.kind {
+                MapObjectKind::Item(kind) => Some(ItemPosition {
+                    sprite_num: object.sprite_num,
+                    kind,
+                    position: object.position,
+                    corners: Corners {
+                        bottom_left: object.position,
+                        top_right: object.position + Vector2::new(object.width, object.height),
+                    },
+                    layers: None,
+                }),
+                _ => None,
+            })
+            .collect();
+        ItemConfig { elements }
+    }
+
+    pub fn climbable_config(&self) -> ClimbablesConfig {
+        let elements = self
+            .objects
+            .iter()
+            .filter_map(|object| match object.kind {
+                MapObjectKind::Climbable(kind) => Some(ClimbablePosition {
+                    kind,
+                    position: object.position,
+                    width: object.width,
+                    height: object.height,
+                    sprite_num: object.sprite_num,
+                    layers: CollisionLayers::climbable(),
+                }),
+                _ => None,
+            })
+            .collect();
+        ClimbablesConfig { elements }
+    }
+
+    pub fn enemy_config(&self) -> EnemiesConfig {
+        let elements = self
+            .objects
+            .iter()
+            .filter_map(|object| match object.kind {
+                MapObjectKind::Enemy => Some(EnemyConfig::new(EnemyKind::Grunt, object.position)),
+                _ => None,
+            })
+            .collect();
+        EnemiesConfig { elements }
+    }
+
+    pub fn arena_config(&self) -> ArenaConfig {
+        ArenaConfig {
+            corners: Corners {
+                bottom_left: Vector2::new(0.0, 0.0),
+                top_right: Vector2::new(self.pixel_width(), self.pixel_height()),
+            },
+        }
+    }
+
+    pub fn player_spawn(&self) -> Option<Vector2<f32>> {
+        self.objects
+            .iter()
+            .find(|object| match object.kind {
+                MapObjectKind::PlayerSpawn => true,
+                _ => false,
+            })
+            .map(|object| object.position)
+    }
+}
+
+/// Tiled stores flip flags in the high bits of each global tile id.
+const TILED_GID_MASK: u32 = 0x1fff_ffff;
+
+#[derive(Debug, Deserialize)]
+struct TiledProperty {
+    name: String,
+    value: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct TiledTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TiledTileset {
+    firstgid: u32,
+    #[serde(default)]
+    tiles: Vec<TiledTile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TiledObject {
+    /// Called `class` from Tiled 1.9 onwards.
+    #[serde(default, rename = "type", alias = "class")]
+    kind: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TiledLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    data: Vec<u32>,
+    #[serde(default)]
+    objects: Vec<TiledObject>,
+}
+
+/// The subset of Tiled's map format we understand, shared by the JSON and TMX importers.
+#[derive(Debug, Deserialize)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    layers: Vec<TiledLayer>,
+    #[serde(default)]
+    tilesets: Vec<TiledTileset>,
+}
+
+fn property<'a>(properties: &'a [TiledProperty], name: &str) -> Option<&'a Value> {
+    properties
+        .iter()
+        .find(|property| property.name == name)
+        .map(|property| &property.value)
+}
+
+fn tile_properties(properties: &[TiledProperty]) -> Result<TileProperties, Error> {
+    let collision = property(properties, "collision")
+        .and_then(Value::as_str)
+        .unwrap_or("Solid");
+    let angle = match property(properties, "angle").and_then(Value::as_str) {
+        Some("Shallow") => SlopeAngle::Shallow,
+        _ => SlopeAngle::Steep,
+    };
+    let base = property(properties, "base")
+        .and_then(Value::as_f64)
+        .unwrap_or(0.0) as f32;
+
+    let (shape, one_way) = match collision {
+        "Solid" => (TileShape::Solid, false),
+        "OneWay" => (TileShape::Solid, true),
+        "None" => (TileShape::Decoration, false),
+        "SlopeUp" => (TileShape::SlopeUp { angle, base }, false),
+        "SlopeDown" => (TileShape::SlopeDown { angle, base }, false),
+        other => {
+            return Err(Error::from_string(format!(
+                "Unknown tile collision `{}`",
+                other
+            )))
+        }
+    };
+    Ok(TileProperties { shape, one_way })
+}
+
+impl TiledMap {
+    fn into_tile_map(self) -> Result<TileMap, Error> {
+        // Tiles come from the ground sprite sheet, which is the first tileset. Any other
+        // tileset would need a sprite sheet of its own.
+        let firstgid = self.tilesets.first().map_or(1, |tileset| tileset.firstgid);
+        let endgid = self
+            .tilesets
+            .iter()
+            .map(|tileset| tileset.firstgid)
+            .filter(|&gid| gid > firstgid)
+            .min()
+            .unwrap_or(TILED_GID_MASK + 1);
+
+        let mut tiles = vec![0; self.width * self.height];
+        let mut objects = Vec::new();
+        let pixel_height = self.height as f32 * self.tileheight;
+
+        for layer in &self.layers {
+            match layer.kind.as_str() {
+                "tilelayer" => {
+                    // Later layers are drawn over earlier ones.
+                    for (cell, &gid) in tiles.iter_mut().zip(layer.data.iter()) {
+                        let gid = gid & TILED_GID_MASK;
+                        if gid == 0 {
+                            continue;
+                        }
+                        if gid < firstgid || gid >= endgid {
+                            return Err(Error::from_string(format!(
+                                "Tile {} is not from the first tileset, the only one supported",
+                                gid
+                            )));
+                        }
+                        *cell = (gid - firstgid) as usize + 1;
+                    }
+                }
+                "objectgroup" => {
+                    for object in &layer.objects {
+                        objects.push(map_object(object, pixel_height)?);
+                    }
+                }
+                other => warn!("Skipping unsupported Tiled layer type `{}`", other),
+            }
+        }
+
+        let mut properties = HashMap::new();
+        if let Some(tileset) = self.tilesets.first() {
+            for tile in &tileset.tiles {
+                properties.insert(tile.id as usize, tile_properties(&tile.properties)?);
+            }
+        }
+
+        Ok(TileMap {
+            tile_width: self.tilewidth,
+            tile_height: self.tileheight,
+            width: self.width,
+            height: self.height,
+            tiles,
+            tile_properties: properties,
+            objects,
+        })
+    }
+
+    fn from_tmx(text: &str) -> Result<Self, Error> {
+        let document = roxmltree::Document::parse(text)?;
+        let map = document.root_element();
+
+        let mut tilesets = Vec::new();
+        let mut layers = Vec::new();
+        for node in map.children().filter(|node| node.is_element()) {
+            match node.tag_name().name() {
+                "tileset" => {
+                    let tiles = node
+                        .children()
+                        .filter(|child| child.has_tag_name("tile"))
+                        .map(|tile| {
+                            Ok(TiledTile {
+                                id: parse_attribute(&tile, "id")?,
+                                properties: tmx_properties(&tile),
+                            })
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    tilesets.push(TiledTileset {
+                        firstgid: parse_attribute(&node, "firstgid")?,
+                        tiles,
+                    });
+                }
+                "layer" => {
+                    let data = node
+                        .children()
+                        .find(|child| child.has_tag_name("data"))
+                        .ok_or_else(|| Error::from_string("TMX layer has no <data>"))?;
+                    if data.attribute("encoding") != Some("csv") {
+                        return Err(Error::from_string(
+                            "Only CSV encoded TMX tile layers are supported",
+                        ));
+                    }
+                    let data = data
+                        .text()
+                        .unwrap_or("")
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|gid| !gid.is_empty())
+                        .map(|gid| {
+                            gid.parse::<u32>().map_err(|_| {
+                                Error::from_string(format!("Invalid tile id `{}` in TMX", gid))
+                            })
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    layers.push(TiledLayer {
+                        kind: "tilelayer".to_string(),
+                        data,
+                        objects: Vec::new(),
+                    });
+                }
+                "objectgroup" => {
+                    let objects = node
+                        .children()
+                        .filter(|child| child.has_tag_name("object"))
+                        .map(|object| {
+                            Ok(TiledObject {
+                                kind: object
+                                    .attribute("type")
+                                    .or_else(|| object.attribute("class"))
+                                    .unwrap_or("")
+                                    .to_string(),
+                                x: parse_attribute(&object, "x")?,
+                                y: parse_attribute(&object, "y")?,
+                                width: parse_attribute(&object, "width").unwrap_or(0.0),
+                                height: parse_attribute(&object, "height").unwrap_or(0.0),
+                                properties: tmx_properties(&object),
+                            })
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    layers.push(TiledLayer {
+                        kind: "objectgroup".to_string(),
+                        data: Vec::new(),
+                        objects,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(TiledMap {
+            width: parse_attribute(&map, "width")?,
+            height: parse_attribute(&map, "height")?,
+            tilewidth: parse_attribute(&map, "tilewidth")?,
+            tileheight: parse_attribute(&map, "tileheight")?,
+            layers,
+            tilesets,
+        })
+    }
+}
+
+fn map_object(object: &TiledObject, pixel_height: f32) -> Result<MapObject, Error> {
+    let sprite_num = property(&object.properties, "sprite_num")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as usize;
+    let kind = match object.kind.as_str() {
+        "PlayerSpawn" => MapObjectKind::PlayerSpawn,
+        "Enemy" => MapObjectKind::Enemy,
+        "Climbable" | "Ladder" => MapObjectKind::Climbable(ClimbableKind::Ladder),
+        "Rope" => MapObjectKind::Climbable(ClimbableKind::Rope),
+        "Background" => MapObjectKind::Item(ItemKind::Background),
+        "Checkpoint" => MapObjectKind::Item(ItemKind::Checkpoint),
+        "Hazard" => MapObjectKind::Item(ItemKind::Hazard),
+        "Goal" => MapObjectKind::Item(ItemKind::Goal),
+        "Collectable" => {
+            let power_up = property(&object.properties, "power_up")
+                .cloned()
+                .ok_or_else(|| Error::from_string("Collectable object has no `power_up`"))?;
+            MapObjectKind::Item(ItemKind::Collectable(serde_json::from_value::<PowerUp>(
+                power_up,
+            )?))
+        }
+        other => {
+            return Err(Error::from_string(format!(
+                "Unknown Tiled object type `{}`",
+                other
+            )))
+        }
+    };
+    Ok(MapObject {
+        kind,
+        // Tiled measures y down from the top of the map to the top of the object.
+        position: Vector2::new(object.x, pixel_height - object.y - object.height),
+        width: object.width,
+        height: object.height,
+        sprite_num,
+    })
+}
+
+fn parse_attribute<T: std::str::FromStr>(node: &roxmltree::Node, name: &str) -> Result<T, Error> {
+    let value = node.attribute(name).ok_or_else(|| {
+        Error::from_string(format!(
+            "TMX <{}> is missing `{}`",
+            node.tag_name().name(),
+            name
+        ))
+    })?;
+    value.parse().map_err(|_| {
+        Error::from_string(format!(
+            "TMX <{}> has an invalid `{}`: {}",
+            node.tag_name().name(),
+            name,
+            value
+        ))
+    })
+}
+
+/// TMX property values are untyped strings, so numbers are recovered by parsing.
+fn tmx_properties(node: &roxmltree::Node) -> Vec<TiledProperty> {
+    node.children()
+        .filter(|child| child.has_tag_name("properties"))
+        .flat_map(|properties| properties.children())
+        .filter(|property| property.has_tag_name("property"))
+        .filter_map(|property| {
+            let name = property.attribute("name")?.to_string();
+            let raw = property.attribute("value").unwrap_or("");
+            let value = serde_json::from_str::<Value>(raw)
+                .ok()
+                .filter(Value::is_number)
+                .unwrap_or_else(|| Value::String(raw.to_string()));
+            Some(TiledProperty { name, value })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::Path};
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+        fs::read(path).unwrap()
+    }
+
+    /// Both fixtures describe the same 3x2 map of 32 pixel tiles.
+    fn check_fixture(map: TileMap) {
+        assert_eq!((map.width, map.height), (3, 2));
+        assert_eq!(map.tile_size(), Vector2::new(32.0, 32.0));
+        // The flipped tile in the bottom left loses its flip flags.
+        assert_eq!(map.tiles, vec![0, 2, 0, 1, 1, 3]);
+
+        let ground = map.ground_config().elements;
+        assert_eq!(ground.len(), 4);
+        assert!(ground.iter().all(|tile| tile.size == Vector2::new(32.0, 32.0)));
+        let top = &ground[0];
+        assert_eq!((top.sprite_num, top.pos), (1, Vector2::new(32.0, 32.0)));
+        assert_eq!(
+            top.shape,
+            TileShape::SlopeUp { angle: SlopeAngle::Shallow, base: 4.0 }
+        );
+        let solid = &ground[2];
+        assert_eq!((solid.sprite_num, solid.pos), (0, Vector2::new(32.0, 0.0)));
+        assert!(!solid.one_way);
+        assert_eq!(solid.shape, TileShape::Solid);
+        let last = &ground[3];
+        assert_eq!((last.sprite_num, last.pos), (2, Vector2::new(64.0, 0.0)));
+        assert!(last.one_way);
+        assert_eq!(last.shape, TileShape::Solid);
+
+        assert_eq!(map.player_spawn(), Some(Vector2::new(8.0, 8.0)));
+        let items = map.item_config().elements;
+        assert_eq!(items.len(), 1);
+        match items[0].kind {
+            ItemKind::Collectable(power_up) => assert_eq!(power_up, PowerUp::KiStar),
+            other => panic!("Expected a collectable, got {:?}", other),
+        }
+        assert_eq!(items[0].sprite_num, 3);
+        assert_eq!(items[0].position, Vector2::new(64.0, 48.0));
+
+        let arena = map.arena_config();
+        assert_eq!(arena.corners.top_right, Vector2::new(96.0, 64.0));
+    }
+
+    #[test]
+    fn imports_tiled_json() {
+        check_fixture(TiledJsonFormat.import_simple(fixture("tiled_map.json")).unwrap());
+    }
+
+    #[test]
+    fn imports_tiled_tmx() {
+        check_fixture(TiledTmxFormat.import_simple(fixture("tiled_map.tmx")).unwrap());
+    }
+
+    fn two_tilesets(data: &str) -> String {
+        format!(
+            r#"<map width="2" height="1" tilewidth="16" tileheight="24">
+                <tileset firstgid="1" name="ground"/>
+                <tileset firstgid="5" name="props"/>
+                <layer><data encoding="csv">{}</data></layer>
+            </map>"#,
+            data
+        )
+    }
+
+    #[test]
+    fn accepts_tiles_from_the_first_tileset() {
+        let map = TiledMap::from_tmx(&two_tilesets("4,0")).unwrap().into_tile_map().unwrap();
+        assert_eq!(map.tiles, vec![4, 0]);
+    }
+
+    #[test]
+    fn rejects_tiles_from_other_tilesets() {
+        let tiled = TiledMap::from_tmx(&two_tilesets("1,5")).unwrap();
+        assert!(tiled.into_tile_map().is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_object_types() {
+        let tiled = TiledMap::from_tmx(
+            r#"<map width="1" height="1" tilewidth="16" tileheight="24">
+                <objectgroup><object type="Dragon" x="0" y="0"/></objectgroup>
+            </map>"#,
+        )
+        .unwrap();
+        assert!(tiled.into_tile_map().is_err());
+    }
+}